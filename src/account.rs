@@ -1,9 +1,14 @@
 use crate::directory::Directory;
 use crate::error::*;
 use crate::helpers::*;
+use crate::jws::eab_jws;
+use crate::jws::key_change_jws;
+use crate::jws::AccountSigner;
+use crate::jws::HmacAlgorithm;
 use openssl::pkey::PKey;
 use openssl::pkey::Private;
 use serde::Deserialize;
+use serde::Serialize;
 use serde_json::json;
 use std::sync::Arc;
 use tracing::field;
@@ -17,7 +22,7 @@ use tracing::Span;
 /// and "revoked". The value "deactivated" should be used to indicate client-
 /// initiated deactivation whereas "revoked" should be used to indicate server-
 /// initiated deactivation.
-#[derive(Deserialize, Eq, PartialEq, Debug, Clone)]
+#[derive(Deserialize, Serialize, Eq, PartialEq, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub enum AccountStatus {
   Valid,
@@ -35,7 +40,7 @@ pub struct Account {
   pub(crate) directory: Option<Arc<Directory>>,
 
   #[serde(skip)]
-  pub(crate) private_key: Option<PKey<Private>>,
+  pub(crate) signer: Option<Arc<dyn AccountSigner>>,
 
   #[serde(skip)]
   /// The account ID of this account.
@@ -49,13 +54,36 @@ pub struct Account {
   /// Including this field in a newAccount request, with a value of true,
   /// indicates the client's agreement with the terms of service.
   pub terms_of_service_agreed: Option<bool>,
-  // TODO(lucacasonato): maybe add support for this
-  // external_account_binding: Option<Value>
-  // TODO(lucacasonato): enable this once LE supports it
-  // /// A URL from which a list of orders submitted by this account can be
-  // /// fetched
-  // #[serde(rename = "orders")]
-  // pub(crate) orders_url: Option<String>,
+  /// A URL from which a list of orders submitted by this account can be
+  /// fetched. Not all ACME servers provide this.
+  #[serde(rename = "orders")]
+  pub(crate) orders_url: Option<String>,
+}
+
+/// A serializable snapshot of an [`Account`], that can be persisted and
+/// later restored without contacting the ACME server.
+///
+/// This is created through [`Account::to_stored`], and restored through
+/// [`Account::from_stored`]. It contains the account private key, so it
+/// should be stored as securely as the key itself.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredAccount {
+  /// The URL of the directory the account belongs to.
+  pub directory_url: String,
+  /// The account ID (URL) of the account.
+  pub id: String,
+  /// The PKCS#8 PEM encoded account private key. This is `None` if the
+  /// account uses a custom [`AccountSigner`] that does not expose its key.
+  pub private_key_pem: Option<String>,
+  /// The status of the account at the time it was stored.
+  pub status: AccountStatus,
+  /// The contact information of the account.
+  pub contact: Option<Vec<String>>,
+  /// If the account agreed to the ACME server terms of service.
+  pub terms_of_service_agreed: Option<bool>,
+  /// The URL of the order list of the account, if any.
+  pub orders_url: Option<String>,
 }
 
 /// An builder that is used to create / retrieve an [`Account`] from the
@@ -64,12 +92,28 @@ pub struct Account {
 pub struct AccountBuilder {
   directory: Arc<Directory>,
 
-  private_key: Option<PKey<Private>>,
+  signer: Option<Arc<dyn AccountSigner>>,
 
   contact: Option<Vec<String>>,
   terms_of_service_agreed: Option<bool>,
   only_return_existing: Option<bool>,
-  // TODO(lucacasonato): externalAccountBinding
+  external_account_binding: Option<ExternalAccountBinding>,
+  external_account_binding_algorithm: HmacAlgorithm,
+}
+
+/// The credentials of an external account (at the CA) that a new ACME
+/// account should be bound to.
+struct ExternalAccountBinding {
+  kid: String,
+  hmac_key: Vec<u8>,
+}
+
+impl std::fmt::Debug for ExternalAccountBinding {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("ExternalAccountBinding")
+      .field("kid", &self.kid)
+      .finish()
+  }
 }
 
 impl AccountBuilder {
@@ -79,17 +123,32 @@ impl AccountBuilder {
   pub fn new(directory: Arc<Directory>) -> Self {
     AccountBuilder {
       directory,
-      private_key: None,
+      signer: None,
       contact: None,
       terms_of_service_agreed: None,
       only_return_existing: None,
+      external_account_binding: None,
+      external_account_binding_algorithm: HmacAlgorithm::HS256,
     }
   }
 
   /// The private key that is used to sign requests to the ACME server. This
   /// may not be the same as a certificate private key.
+  ///
+  /// RSA keys (signed with `RS256`), P-256 / P-384 EC keys (signed with
+  /// `ES256` / `ES384`) and Ed25519 keys (signed with `EdDSA`) are supported.
   pub fn private_key(&mut self, private_key: PKey<Private>) -> &mut Self {
-    self.private_key = Some(private_key);
+    self.signer = Some(Arc::new(private_key));
+    self
+  }
+
+  /// A custom [`AccountSigner`] that is used to sign requests to the ACME
+  /// server. This can be used to keep the account key outside of process
+  /// memory (for example in a HSM or KMS).
+  ///
+  /// This replaces any key set through [`AccountBuilder::private_key`].
+  pub fn signer(&mut self, signer: Arc<dyn AccountSigner>) -> &mut Self {
+    self.signer = Some(signer);
     self
   }
 
@@ -120,34 +179,84 @@ impl AccountBuilder {
     self
   }
 
+  /// Bind the new account to an existing account at the CA (external
+  /// account binding). Some ACME servers require this to create an account.
+  ///
+  /// The key ID and MAC key are provided by the CA. The `hmac_key` must be
+  /// the raw key, so if the CA hands it out base64url encoded it must be
+  /// decoded first. The binding is signed with `HS256`, unless a different
+  /// algorithm is set through
+  /// [`AccountBuilder::external_account_binding_algorithm`].
+  pub fn external_account_binding(
+    &mut self,
+    kid: String,
+    hmac_key: Vec<u8>,
+  ) -> &mut Self {
+    self.external_account_binding =
+      Some(ExternalAccountBinding { kid, hmac_key });
+    self
+  }
+
+  /// The MAC algorithm used to sign the external account binding. Defaults
+  /// to `HS256`.
+  pub fn external_account_binding_algorithm(
+    &mut self,
+    algorithm: HmacAlgorithm,
+  ) -> &mut Self {
+    self.external_account_binding_algorithm = algorithm;
+    self
+  }
+
   /// This will create / retrieve an [`Account`] from the ACME server.
   ///
-  /// If the [`AccountBuilder`] does not contain a private key, a new
-  /// 4096 bit RSA key will be generated (using the system random). If
+  /// If the [`AccountBuilder`] does not contain a private key or signer, a
+  /// new 4096 bit RSA key will be generated (using the system random). If
   /// a key is generated, it can be retrieved from the created [`Account`]
   /// through the [`Account::private_key`] method.
-  #[instrument(level = Level::INFO, name = "acme2::AccountBuilder::build", err, skip(self), fields(contact = ?self.contact, terms_of_service_agreed = ?self.terms_of_service_agreed, only_return_existing = ?self.only_return_existing, private_key_id = field::Empty))]
+  #[instrument(level = Level::INFO, name = "acme2::AccountBuilder::build", err, skip(self), fields(contact = ?self.contact, terms_of_service_agreed = ?self.terms_of_service_agreed, only_return_existing = ?self.only_return_existing, external_account_binding = self.external_account_binding.is_some(), private_key_id = field::Empty))]
   pub async fn build(&mut self) -> Result<Arc<Account>, Error> {
-    let private_key = if let Some(private_key) = self.private_key.clone() {
-      private_key
-    } else {
-      gen_rsa_private_key(4096)?
-    };
+    let external_account_required = self
+      .directory
+      .meta
+      .as_ref()
+      .and_then(|meta| meta.external_account_required)
+      .unwrap_or(false);
+    if external_account_required
+      && self.external_account_binding.is_none()
+      && self.only_return_existing != Some(true)
+    {
+      return Err(Error::Validation(
+        "the ACME server requires an external account binding, but none was provided",
+      ));
+    }
+
+    let signer: Arc<dyn AccountSigner> =
+      if let Some(signer) = self.signer.clone() {
+        signer
+      } else {
+        Arc::new(gen_rsa_private_key(4096)?)
+      };
 
     let url = self.directory.new_account_url.clone();
 
+    let mut payload = json!({
+      "contact": self.contact,
+      "termsOfServiceAgreed": self.terms_of_service_agreed,
+      "onlyReturnExisting": self.only_return_existing
+    });
+    if let Some(eab) = &self.external_account_binding {
+      payload["externalAccountBinding"] = eab_jws(
+        &url,
+        &eab.kid,
+        &eab.hmac_key,
+        self.external_account_binding_algorithm,
+        &signer.public_jwk()?,
+      )?;
+    }
+
     let (res, headers) = self
       .directory
-      .authenticated_request::<_, Account>(
-        &url,
-        json!({
-          "contact": self.contact,
-          "termsOfServiceAgreed": self.terms_of_service_agreed,
-          "onlyReturnExisting": self.only_return_existing
-        }),
-        private_key.clone(),
-        None,
-      )
+      .authenticated_request::<_, Account>(&url, payload, &*signer, None)
       .await?;
     let res: Result<Account, Error> = res.into();
     let mut acc = res?;
@@ -161,10 +270,10 @@ impl AccountBuilder {
         .to_str(),
     )?
     .to_string();
-    Span::current().record("account_id", &field::display(&account_id));
+    Span::current().record("account_id", field::display(&account_id));
 
     acc.directory = Some(self.directory.clone());
-    acc.private_key = Some(private_key);
+    acc.signer = Some(signer);
     acc.id = account_id;
     Ok(Arc::new(acc))
   }
@@ -172,7 +281,184 @@ impl AccountBuilder {
 
 impl Account {
   /// Retrieve the private key for this account.
-  pub fn private_key(&self) -> PKey<Private> {
-    self.private_key.clone().unwrap()
+  ///
+  /// This returns `None` if the account uses a custom [`AccountSigner`] that
+  /// does not expose its private key.
+  pub fn private_key(&self) -> Option<PKey<Private>> {
+    self.signer().private_key()
+  }
+
+  /// Retrieve the signer used to sign requests for this account.
+  pub fn signer(&self) -> Arc<dyn AccountSigner> {
+    self.signer.clone().unwrap()
+  }
+
+  /// Create a serializable snapshot of this account.
+  ///
+  /// If the account uses a custom [`AccountSigner`] that does not expose
+  /// its private key, the snapshot will not contain a key, and must be
+  /// restored through [`Account::from_stored_with_signer`].
+  pub fn to_stored(&self) -> Result<StoredAccount, Error> {
+    let private_key_pem = match self.private_key() {
+      Some(pkey) => Some(
+        String::from_utf8(pkey.private_key_to_pem_pkcs8()?)
+          .map_err(|err| Error::Other(Box::new(err)))?,
+      ),
+      None => None,
+    };
+
+    Ok(StoredAccount {
+      directory_url: self.directory.as_ref().unwrap().url().to_string(),
+      id: self.id.clone(),
+      private_key_pem,
+      status: self.status.clone(),
+      contact: self.contact.clone(),
+      terms_of_service_agreed: self.terms_of_service_agreed,
+      orders_url: self.orders_url.clone(),
+    })
+  }
+
+  /// Restore an account from a snapshot created by [`Account::to_stored`],
+  /// without contacting the ACME server.
+  ///
+  /// The directory must be the same directory the account was created
+  /// with. The snapshot must contain the account private key.
+  pub fn from_stored(
+    directory: Arc<Directory>,
+    stored: StoredAccount,
+  ) -> Result<Arc<Account>, Error> {
+    let pem = stored.private_key_pem.clone().ok_or(Error::Validation(
+      "the stored account does not contain a private key",
+    ))?;
+    let private_key = PKey::private_key_from_pem(pem.as_bytes())?;
+    Self::from_stored_with_signer(directory, stored, Arc::new(private_key))
+  }
+
+  /// Restore an account from a snapshot created by [`Account::to_stored`],
+  /// using a custom [`AccountSigner`] for the account key. This does not
+  /// contact the ACME server.
+  ///
+  /// The directory must be the same directory the account was created
+  /// with.
+  pub fn from_stored_with_signer(
+    directory: Arc<Directory>,
+    stored: StoredAccount,
+    signer: Arc<dyn AccountSigner>,
+  ) -> Result<Arc<Account>, Error> {
+    if stored.directory_url != directory.url() {
+      return Err(Error::Validation(
+        "the stored account belongs to a different directory",
+      ));
+    }
+
+    Ok(Arc::new(Account {
+      directory: Some(directory),
+      signer: Some(signer),
+      id: stored.id,
+      status: stored.status,
+      contact: stored.contact,
+      terms_of_service_agreed: stored.terms_of_service_agreed,
+      orders_url: stored.orders_url,
+    }))
+  }
+
+  /// Roll over the account key to a new private key.
+  ///
+  /// The returned [`Account`] uses the new key. The old key is no longer
+  /// valid for this account once this completes.
+  pub async fn change_key(
+    &self,
+    new_key: PKey<Private>,
+  ) -> Result<Arc<Account>, Error> {
+    self.change_key_with_signer(Arc::new(new_key)).await
+  }
+
+  /// Roll over the account key to a key held by a custom [`AccountSigner`].
+  ///
+  /// The returned [`Account`] uses the new signer. The old key is no longer
+  /// valid for this account once this completes.
+  #[instrument(level = Level::INFO, name = "acme2::Account::change_key", err, skip(self, new_signer), fields(account_id = %self.id))]
+  pub async fn change_key_with_signer(
+    &self,
+    new_signer: Arc<dyn AccountSigner>,
+  ) -> Result<Arc<Account>, Error> {
+    let directory = self.directory.clone().unwrap();
+    let url = directory.key_change_url.clone();
+
+    let inner = key_change_jws(
+      &url,
+      &self.id,
+      &self.signer().public_jwk()?,
+      &*new_signer,
+    )
+    .await?;
+
+    let payload = serde_json::to_string(&inner)?;
+    directory
+      .authenticated_request_bytes(
+        &url,
+        &payload,
+        &*self.signer(),
+        &Some(self.id.clone()),
+      )
+      .await?
+      .0?;
+
+    let mut account = self.clone();
+    account.signer = Some(new_signer);
+    Ok(Arc::new(account))
+  }
+
+  /// Replace the contact information of this account. For example this
+  /// could be a `vec!["mailto:hello@lcas.dev".to_string()]`. An empty list
+  /// removes all contact information.
+  #[instrument(level = Level::INFO, name = "acme2::Account::update_contact", err, skip(self), fields(account_id = %self.id, status = field::Empty))]
+  pub async fn update_contact(
+    &self,
+    contact: Vec<String>,
+  ) -> Result<Arc<Account>, Error> {
+    self.update(json!({ "contact": contact })).await
+  }
+
+  /// Agree to the ACME server terms of service. This is needed if the terms
+  /// of service have changed since the account was created.
+  #[instrument(level = Level::INFO, name = "acme2::Account::agree_to_terms", err, skip(self), fields(account_id = %self.id, status = field::Empty))]
+  pub async fn agree_to_terms(&self) -> Result<Arc<Account>, Error> {
+    self.update(json!({ "termsOfServiceAgreed": true })).await
+  }
+
+  /// Deactivate this account. After this the account can not be used for any
+  /// further operations, and the account key can not be used to create a
+  /// new account on the same ACME server.
+  ///
+  /// The returned [`Account`] will have the [`AccountStatus::Deactivated`]
+  /// status.
+  #[instrument(level = Level::INFO, name = "acme2::Account::deactivate", err, skip(self), fields(account_id = %self.id, status = field::Empty))]
+  pub async fn deactivate(&self) -> Result<Arc<Account>, Error> {
+    self.update(json!({ "status": "deactivated" })).await
+  }
+
+  async fn update(
+    &self,
+    payload: serde_json::Value,
+  ) -> Result<Arc<Account>, Error> {
+    let directory = self.directory.clone().unwrap();
+
+    let (res, _) = directory
+      .authenticated_request::<_, Account>(
+        &self.id,
+        payload,
+        &*self.signer(),
+        Some(self.id.clone()),
+      )
+      .await?;
+    let res: Result<Account, Error> = res.into();
+    let mut account = res?;
+    Span::current().record("status", field::debug(&account.status));
+
+    account.directory = Some(directory);
+    account.signer = self.signer.clone();
+    account.id = self.id.clone();
+    Ok(Arc::new(account))
   }
 }
@@ -2,10 +2,20 @@ use crate::account::Account;
 use crate::error::*;
 use crate::helpers::Identifier;
 use crate::helpers::*;
-use crate::jws::Jwk;
 use crate::order::Order;
+use openssl::asn1::Asn1Object;
+use openssl::asn1::Asn1OctetString;
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::bn::MsbOption;
 use openssl::hash::hash;
 use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::pkey::Private;
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::X509Extension;
+use openssl::x509::X509Name;
+use openssl::x509::X509;
 use serde::Deserialize;
 use serde_json::json;
 use std::sync::Arc;
@@ -16,6 +26,17 @@ use tracing::instrument;
 use tracing::Level;
 use tracing::Span;
 
+/// The path prefix under which the ACME server requests the key
+/// authorization of a HTTP01 challenge.
+pub const ACME_CHALLENGE_PATH_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// The ALPN protocol name an ACME server negotiates when validating a
+/// `tls-alpn-01` challenge (RFC 8737).
+pub const ACME_TLS_ALPN_PROTOCOL: &[u8] = b"acme-tls/1";
+
+/// The OID of the `id-pe-acmeIdentifier` certificate extension.
+const ID_PE_ACME_IDENTIFIER: &str = "1.3.6.1.5.5.7.1.31";
+
 #[derive(Deserialize, Debug, Eq, PartialEq)]
 #[serde(rename_all = "camelCase")]
 /// The status of this authorization.
@@ -80,6 +101,8 @@ pub enum ChallengeStatus {
 pub struct Challenge {
   #[serde(skip)]
   pub(crate) account: Option<Arc<Account>>,
+  #[serde(skip)]
+  pub(crate) identifier: Option<Identifier>,
 
   /// The type of challenge encoded in the object.
   pub r#type: String,
@@ -116,7 +139,7 @@ impl Order {
         .authenticated_request::<_, Authorization>(
           &authorization_url,
           "",
-          account.private_key.clone().unwrap(),
+          &*account.signer(),
           Some(account.id.clone()),
         )
         .await?;
@@ -127,7 +150,8 @@ impl Order {
       authorization.account = Some(account.clone());
       authorization.url = authorization_url;
       for challenge in &mut authorization.challenges {
-        challenge.account = Some(account.clone())
+        challenge.account = Some(account.clone());
+        challenge.identifier = Some(authorization.identifier.clone());
       }
       authorizations.push(authorization)
     }
@@ -139,7 +163,7 @@ impl Order {
 impl Authorization {
   /// Get a certain type of challenge to complete.
   ///
-  /// Example: `http-01`, or `dns-01`
+  /// Example: `http-01`, `dns-01`, or `tls-alpn-01`
   pub fn get_challenge(&self, r#type: &str) -> Option<Challenge> {
     for challenge in &self.challenges {
       if challenge.r#type == r#type {
@@ -161,7 +185,7 @@ impl Authorization {
       .authenticated_request::<_, Authorization>(
         &self.url,
         json!(""),
-        account.private_key.clone().unwrap(),
+        &*account.signer(),
         Some(account.id.clone()),
       )
       .await?;
@@ -169,7 +193,11 @@ impl Authorization {
     let mut authorization = res?;
     authorization.url = self.url.clone();
     authorization.account = Some(account.clone());
-    Span::current().record("status", &field::debug(&authorization.status));
+    for challenge in &mut authorization.challenges {
+      challenge.account = Some(account.clone());
+      challenge.identifier = Some(authorization.identifier.clone());
+    }
+    Span::current().record("status", field::debug(&authorization.status));
     Ok(authorization)
   }
 
@@ -220,17 +248,8 @@ impl Challenge {
     if let Some(token) = self.token.clone() {
       let account = self.account.clone().unwrap();
 
-      let key_authorization = format!(
-        "{}.{}",
-        token,
-        b64(&hash(
-          MessageDigest::sha256(),
-          &serde_json::to_string(&Jwk::new(
-            &account.private_key.clone().unwrap()
-          ))?
-          .into_bytes()
-        )?)
-      );
+      let key_authorization =
+        format!("{}.{}", token, account.signer().public_jwk()?.thumbprint()?);
 
       Ok(Some(key_authorization))
     } else {
@@ -253,6 +272,69 @@ impl Challenge {
     }
   }
 
+  /// The self-signed certificate that should be served for the TLS-ALPN01
+  /// challenge, together with its private key.
+  ///
+  /// The certificate must be presented on port 443 for TLS connections that
+  /// negotiate the [`ACME_TLS_ALPN_PROTOCOL`] ALPN protocol, and that request
+  /// the identifier of the authorization as SNI (see
+  /// [`Identifier::tls_server_name`]).
+  pub fn tls_alpn01_certificate(
+    &self,
+  ) -> Result<Option<(PKey<Private>, X509)>, Error> {
+    let key_authorization = match self.key_authorization()? {
+      Some(key_authorization) => key_authorization,
+      None => return Ok(None),
+    };
+    let identifier = self.identifier.clone().ok_or(Error::Validation(
+      "the challenge was not retrieved through an authorization",
+    ))?;
+
+    let digest = hash(MessageDigest::sha256(), key_authorization.as_bytes())?;
+    // The extension value is the DER encoded `Authorization ::= OCTET
+    // STRING (SIZE (32))` containing the key authorization digest.
+    let mut acme_identifier = vec![0x04, digest.len() as u8];
+    acme_identifier.extend_from_slice(&digest);
+
+    let pkey = gen_ec_p256_private_key()?;
+    let mut name = X509Name::builder()?;
+    name
+      .append_entry_by_text("CN", "ACME TLS-ALPN-01 Challenge Certificate")?;
+    let name = name.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    let serial = serial.to_asn1_integer()?;
+    builder.set_serial_number(&serial)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&pkey)?;
+    builder.set_not_before(&*Asn1Time::days_from_now(0)?)?;
+    builder.set_not_after(&*Asn1Time::days_from_now(7)?)?;
+
+    let san_extension = {
+      let mut san = SubjectAlternativeName::new();
+      if identifier.is_ip() {
+        san.ip(&identifier.value);
+      } else {
+        san.dns(&identifier.value);
+      }
+      san.build(&builder.x509v3_context(None, None))?
+    };
+    builder.append_extension(san_extension)?;
+    let oid = Asn1Object::from_str(ID_PE_ACME_IDENTIFIER)?;
+    let value = Asn1OctetString::new_from_bytes(&acme_identifier)?;
+    builder
+      .append_extension(X509Extension::new_from_der(&oid, true, &value)?)?;
+
+    builder.sign(&pkey, MessageDigest::sha256())?;
+
+    Ok(Some((pkey, builder.build())))
+  }
+
   /// Initiate validation of the challenge by the ACME server.
   ///
   /// Before calling this method, you should have set up your challenge token
@@ -270,14 +352,15 @@ impl Challenge {
       .authenticated_request::<_, Challenge>(
         &self.url,
         json!({}),
-        account.private_key.clone().unwrap(),
+        &*account.signer(),
         Some(account.id.clone()),
       )
       .await?;
     let res: Result<Challenge, Error> = res.into();
     let mut challenge = res?;
     challenge.account = Some(account.clone());
-    Span::current().record("status", &field::debug(&challenge.status));
+    challenge.identifier = self.identifier.clone();
+    Span::current().record("status", field::debug(&challenge.status));
 
     Ok(challenge)
   }
@@ -294,14 +377,15 @@ impl Challenge {
       .authenticated_request::<_, Challenge>(
         &self.url,
         json!(""),
-        account.private_key.clone().unwrap(),
+        &*account.signer(),
         Some(account.id.clone()),
       )
       .await?;
     let res: Result<Challenge, Error> = res.into();
     let mut challenge = res?;
     challenge.account = Some(account.clone());
-    Span::current().record("status", &field::debug(&challenge.status));
+    challenge.identifier = self.identifier.clone();
+    Span::current().record("status", field::debug(&challenge.status));
     Ok(challenge)
   }
 
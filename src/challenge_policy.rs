@@ -0,0 +1,140 @@
+use crate::authorization::Authorization;
+use crate::authorization::Challenge;
+use crate::error::*;
+use crate::helpers::normalize_name;
+
+/// A policy that decides which challenge type to use for an authorization,
+/// based on its identifier.
+///
+/// Every rule maps an identifier pattern to challenge types in order of
+/// preference. The first matching rule wins, and authorizations that do not
+/// match any rule use the default preference.
+///
+/// ```rust
+/// # use acme2::ChallengePolicy;
+/// let mut policy = ChallengePolicy::new(&["tls-alpn-01", "http-01"]);
+/// policy
+///   .wildcard(&["dns-01"])
+///   .rule("*.internal", &["http-01"]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChallengePolicy {
+  default: Vec<String>,
+  wildcard: Option<Vec<String>>,
+  rules: Vec<(String, Vec<String>)>,
+}
+
+impl ChallengePolicy {
+  /// Create a policy that uses `types` for every authorization that does
+  /// not match a more specific rule.
+  pub fn new(types: &[&str]) -> Self {
+    ChallengePolicy {
+      default: to_owned(types),
+      wildcard: None,
+      rules: vec![],
+    }
+  }
+
+  /// The challenge types used for wildcard authorizations (see
+  /// [`Authorization::wildcard`]). This takes precedence over all rules.
+  pub fn wildcard(&mut self, types: &[&str]) -> &mut Self {
+    self.wildcard = Some(to_owned(types));
+    self
+  }
+
+  /// Add a rule that uses `types` for identifiers matching `pattern`.
+  ///
+  /// The pattern is either an exact domain name or IP address, or a domain
+  /// name prefixed with `*.` that matches all of its subdomains (at any
+  /// depth, but not the domain itself). Domain names are compared case
+  /// insensitively.
+  pub fn rule(&mut self, pattern: &str, types: &[&str]) -> &mut Self {
+    self.rules.push((normalize_name(pattern), to_owned(types)));
+    self
+  }
+
+  /// The challenge types the policy prefers for the authorization, in order
+  /// of preference.
+  pub fn preferred_types(&self, authorization: &Authorization) -> &[String] {
+    if authorization.wildcard == Some(true) {
+      if let Some(types) = &self.wildcard {
+        return types;
+      }
+    }
+    let name = identifier_name(authorization);
+    self
+      .rules
+      .iter()
+      .find(|(pattern, _)| matches(pattern, &name))
+      .map(|(_, types)| types)
+      .unwrap_or(&self.default)
+  }
+
+  /// Pick the most preferred challenge that the ACME server offered for the
+  /// authorization.
+  ///
+  /// Returns [`Error::NoAcceptableChallenge`] if none of the preferred
+  /// types is offered.
+  pub fn select(
+    &self,
+    authorization: &Authorization,
+  ) -> Result<Challenge, Error> {
+    self.select_supported(authorization, None)
+  }
+
+  /// Like [`ChallengePolicy::select`], but only considers challenge types in
+  /// `supported`, if given.
+  pub(crate) fn select_supported(
+    &self,
+    authorization: &Authorization,
+    supported: Option<&[&str]>,
+  ) -> Result<Challenge, Error> {
+    self
+      .preferred_types(authorization)
+      .iter()
+      .filter(|r#type| match supported {
+        Some(supported) => supported.contains(&r#type.as_str()),
+        None => true,
+      })
+      .find_map(|r#type| authorization.get_challenge(r#type))
+      .ok_or_else(|| no_acceptable_challenge(authorization))
+  }
+}
+
+/// The error returned when none of the challenges offered for the
+/// authorization are acceptable.
+pub(crate) fn no_acceptable_challenge(authorization: &Authorization) -> Error {
+  Error::NoAcceptableChallenge {
+    identifier: identifier_name(authorization),
+    offered: authorization
+      .challenges
+      .iter()
+      .map(|challenge| challenge.r#type.clone())
+      .collect(),
+  }
+}
+
+/// The identifier of the authorization as it was requested, so including the
+/// `*.` prefix for wildcards.
+fn identifier_name(authorization: &Authorization) -> String {
+  let name = normalize_name(&authorization.identifier.value);
+  if authorization.wildcard == Some(true) {
+    format!("*.{}", name)
+  } else {
+    name
+  }
+}
+
+fn matches(pattern: &str, name: &str) -> bool {
+  match pattern.strip_prefix("*.") {
+    Some(suffix) => match name.strip_suffix(suffix) {
+      Some(prefix) => prefix.len() > 1 && prefix.ends_with('.'),
+      None => false,
+    },
+    None => pattern == name,
+  }
+}
+
+fn to_owned(types: &[&str]) -> Vec<String> {
+  types.iter().map(|r#type| r#type.to_string()).collect()
+}
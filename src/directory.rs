@@ -1,11 +1,11 @@
 use crate::error::*;
 use crate::jws::jws;
+use crate::jws::AccountSigner;
 use hyper::body::Bytes;
-use openssl::pkey::PKey;
-use openssl::pkey::Private;
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::Mutex;
 use tracing::debug;
@@ -52,20 +52,18 @@ impl DirectoryBuilder {
     fields(url = %self.url, custom_http_client = self.http_client.is_some(), dir = field::Empty)
   )]
   pub async fn build(&mut self) -> Result<Arc<Directory>, Error> {
-    let http_client = self
-      .http_client
-      .clone()
-      .unwrap_or_else(reqwest::Client::new);
+    let http_client = self.http_client.clone().unwrap_or_default();
 
     let resp = http_client.get(&self.url).send().await?;
 
     let res: Result<Directory, Error> =
       resp.json::<ServerResult<Directory>>().await?.into();
     let mut dir = res?;
-    Span::current().record("dir", &field::debug(&dir));
+    Span::current().record("dir", field::debug(&dir));
 
     dir.http_client = http_client;
     dir.nonce = Mutex::new(None);
+    dir.url = self.url.clone();
 
     Ok(Arc::new(dir))
   }
@@ -77,6 +75,8 @@ impl DirectoryBuilder {
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Directory {
+  #[serde(skip)]
+  pub(crate) url: String,
   #[serde(skip)]
   pub(crate) http_client: reqwest::Client,
   #[serde(skip)]
@@ -91,8 +91,8 @@ pub struct Directory {
   pub(crate) revoke_cert_url: String,
   #[serde(rename = "keyChange")]
   pub(crate) key_change_url: String,
-  #[serde(rename = "newAuthz")]
-  pub(crate) new_authz_url: Option<String>,
+  #[serde(rename = "renewalInfo")]
+  pub(crate) renewal_info_url: Option<String>,
   /// Optional metadata describing a directory.
   pub meta: Option<DirectoryMeta>,
 }
@@ -107,6 +107,9 @@ pub struct DirectoryMeta {
   pub website: Option<String>,
   pub caa_identities: Option<Vec<String>>,
   pub external_account_required: Option<bool>,
+  /// The certificate profiles offered by the ACME server, mapping the
+  /// profile name to a human readable description.
+  pub profiles: Option<HashMap<String, String>>,
 }
 
 fn extract_nonce_from_response(
@@ -126,6 +129,11 @@ fn extract_nonce_from_response(
 }
 
 impl Directory {
+  /// The URL of this directory.
+  pub fn url(&self) -> &str {
+    &self.url
+  }
+
   #[instrument(
     level = Level::DEBUG,
     name = "acme2::Directory::get_nonce",
@@ -136,10 +144,10 @@ impl Directory {
   pub(crate) async fn get_nonce(&self) -> Result<String, Error> {
     let maybe_nonce = {
       let mut guard = self.nonce.lock().unwrap();
-      std::mem::replace(&mut *guard, None)
+      guard.take()
     };
     let span = Span::current();
-    span.record("cached", &maybe_nonce.is_some());
+    span.record("cached", maybe_nonce.is_some());
     if let Some(nonce) = maybe_nonce {
       return Ok(nonce);
     }
@@ -151,16 +159,16 @@ impl Directory {
     }
   }
 
-  #[instrument(level = Level::DEBUG, name = "acme2::Directory::authenticated_request_raw", err, skip(self, payload, pkey))]
+  #[instrument(level = Level::DEBUG, name = "acme2::Directory::authenticated_request_raw", err, skip(self, payload, signer))]
   async fn authenticated_request_raw(
     &self,
     url: &str,
     payload: &str,
-    pkey: &PKey<Private>,
+    signer: &dyn AccountSigner,
     account_id: &Option<String>,
   ) -> Result<reqwest::Response, Error> {
     let nonce = self.get_nonce().await?;
-    let body = jws(url, nonce, &payload, pkey, account_id.clone())?;
+    let body = jws(url, nonce, payload, signer, account_id.clone()).await?;
     let resp = self
       .http_client
       .post(url)
@@ -181,14 +189,14 @@ impl Directory {
     level = Level::DEBUG,
     name = "acme2::Directory::authenticated_request_bytes",
     err,
-    skip(self, payload, pkey),
+    skip(self, payload, signer),
     fields()
   )]
   pub(crate) async fn authenticated_request_bytes(
     &self,
     url: &str,
     payload: &str,
-    pkey: &PKey<Private>,
+    signer: &dyn AccountSigner,
     account_id: &Option<String>,
   ) -> Result<(Result<Bytes, ServerError>, reqwest::header::HeaderMap), Error>
   {
@@ -198,7 +206,7 @@ impl Directory {
       attempt += 1;
 
       let resp = self
-        .authenticated_request_raw(url, &payload, &pkey, &account_id)
+        .authenticated_request_raw(url, payload, signer, account_id)
         .await?;
 
       let headers = resp.headers().clone();
@@ -224,14 +232,14 @@ impl Directory {
     level = Level::DEBUG,
     name = "acme2::Directory::authenticated_request",
     err,
-    skip(self, payload, pkey),
+    skip(self, payload, signer),
     fields()
   )]
   pub(crate) async fn authenticated_request<T, R>(
     &self,
     url: &str,
     payload: T,
-    pkey: PKey<Private>,
+    signer: &dyn AccountSigner,
     account_id: Option<String>,
   ) -> Result<(ServerResult<R>, reqwest::header::HeaderMap), Error>
   where
@@ -246,7 +254,7 @@ impl Directory {
     };
 
     let (res, headers) = self
-      .authenticated_request_bytes(url, &payload, &pkey, &account_id)
+      .authenticated_request_bytes(url, &payload, signer, &account_id)
       .await?;
 
     let bytes = match res {
@@ -0,0 +1,155 @@
+use crate::authorization::Authorization;
+use crate::authorization::Challenge;
+use crate::dns_delegation::CnameDelegation;
+#[cfg(feature = "dns-propagation")]
+use crate::dns_propagation::DnsPropagationCheck;
+use crate::error::*;
+use crate::solver::ChallengeSolver;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// A provider that can create and remove TXT records in a DNS zone, used to
+/// fulfill DNS01 challenges.
+///
+/// Record names are fully qualified domain names without a trailing dot,
+/// for example `_acme-challenge.example.com`.
+#[async_trait]
+pub trait DnsProvider: Debug + Send + Sync {
+  /// Add a TXT record with the given value. Existing TXT records with the
+  /// same name should be left in place.
+  async fn set_txt_record(&self, name: &str, value: &str) -> Result<(), Error>;
+
+  /// Remove the TXT record with the given name and value.
+  async fn remove_txt_record(
+    &self,
+    name: &str,
+    value: &str,
+  ) -> Result<(), Error>;
+}
+
+/// A [`ChallengeSolver`] for DNS01 challenges, that creates the
+/// `_acme-challenge` TXT records through a [`DnsProvider`].
+#[derive(Debug, Clone)]
+pub struct Dns01Solver {
+  provider: Arc<dyn DnsProvider>,
+  cname_delegation: Option<CnameDelegation>,
+  /// The delegated record names resolved in `present`, by challenge token,
+  /// so `cleanup` removes the record that was actually created.
+  delegated_names: Arc<Mutex<HashMap<String, String>>>,
+  #[cfg(feature = "dns-propagation")]
+  propagation_check: Option<DnsPropagationCheck>,
+}
+
+impl Dns01Solver {
+  /// Create a solver that uses `provider` to manage TXT records.
+  pub fn new(provider: Arc<dyn DnsProvider>) -> Self {
+    Dns01Solver {
+      provider,
+      cname_delegation: None,
+      delegated_names: Arc::new(Mutex::new(HashMap::new())),
+      #[cfg(feature = "dns-propagation")]
+      propagation_check: None,
+    }
+  }
+
+  /// Follow CNAME records at `_acme-challenge.<domain>`, and create the TXT
+  /// record at the end of the chain instead. The chain is resolved once when
+  /// the record is created, and the same name is used to remove it.
+  pub fn cname_delegation(
+    &mut self,
+    cname_delegation: CnameDelegation,
+  ) -> &mut Self {
+    self.cname_delegation = Some(cname_delegation);
+    self
+  }
+
+  /// Wait for the TXT record to propagate to all authoritative nameservers
+  /// after creating it, before the ACME server is asked to validate the
+  /// challenge.
+  #[cfg(feature = "dns-propagation")]
+  pub fn propagation_check(
+    &mut self,
+    propagation_check: DnsPropagationCheck,
+  ) -> &mut Self {
+    self.propagation_check = Some(propagation_check);
+    self
+  }
+
+  /// The name and value of the TXT record for a challenge, before any CNAME
+  /// delegation.
+  fn record(
+    &self,
+    authorization: &Authorization,
+    challenge: &Challenge,
+  ) -> Result<(String, String), Error> {
+    let name =
+      authorization
+        .identifier
+        .dns01_record_name()
+        .ok_or(Error::Validation(
+          "dns-01 challenges can not be used for ip identifiers",
+        ))?;
+    let value = challenge
+      .key_authorization_encoded()?
+      .ok_or(Error::Validation("the challenge does not have a token"))?;
+    Ok((name, value))
+  }
+}
+
+#[async_trait]
+impl ChallengeSolver for Dns01Solver {
+  fn supported_types(&self) -> &[&str] {
+    &["dns-01"]
+  }
+
+  async fn present(
+    &self,
+    authorization: &Authorization,
+    challenge: &Challenge,
+  ) -> Result<(), Error> {
+    let (name, value) = self.record(authorization, challenge)?;
+    let name = match &self.cname_delegation {
+      Some(cname_delegation) => {
+        let name = cname_delegation.resolve(&name).await?;
+        if let Some(token) = &challenge.token {
+          self
+            .delegated_names
+            .lock()
+            .unwrap()
+            .insert(token.clone(), name.clone());
+        }
+        name
+      }
+      None => name,
+    };
+    self.provider.set_txt_record(&name, &value).await?;
+    #[cfg(feature = "dns-propagation")]
+    if let Some(propagation_check) = &self.propagation_check {
+      propagation_check.wait(&name, &value).await?;
+    }
+    Ok(())
+  }
+
+  async fn cleanup(
+    &self,
+    authorization: &Authorization,
+    challenge: &Challenge,
+  ) -> Result<(), Error> {
+    let (name, value) = self.record(authorization, challenge)?;
+    let delegated_name = challenge
+      .token
+      .as_ref()
+      .and_then(|token| self.delegated_names.lock().unwrap().remove(token));
+    // Only resolve the chain again if the record was not created by this
+    // solver.
+    let name = match (delegated_name, &self.cname_delegation) {
+      (Some(name), _) => name,
+      (None, Some(cname_delegation)) => cname_delegation.resolve(&name).await?,
+      (None, None) => name,
+    };
+    self.provider.remove_txt_record(&name, &value).await
+  }
+}
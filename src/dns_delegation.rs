@@ -0,0 +1,116 @@
+#[cfg(any(feature = "rfc2136", feature = "dns-propagation"))]
+use crate::dns_wire::*;
+use crate::error::*;
+use crate::helpers::normalize_name;
+use std::collections::HashMap;
+use std::collections::HashSet;
+#[cfg(any(feature = "rfc2136", feature = "dns-propagation"))]
+use std::net::SocketAddr;
+#[cfg(any(feature = "rfc2136", feature = "dns-propagation"))]
+use std::time::Duration;
+use tracing::debug;
+use tracing::instrument;
+use tracing::Level;
+
+/// The maximum number of CNAME records that are followed.
+const MAX_CHAIN_LENGTH: usize = 16;
+
+/// Resolves the CNAME chain of `_acme-challenge` records, for setups that
+/// delegate DNS01 validation to a dedicated zone (like acme-dns).
+///
+/// ACME servers follow CNAME records when looking up the TXT record of a
+/// DNS01 challenge, so the record has to be created at the end of the chain.
+/// Use this with [`Dns01Solver::cname_delegation`].
+///
+/// The chain is either looked up live through recursive resolvers (requires
+/// the `rfc2136` or `dns-propagation` feature), or taken from a static map.
+///
+/// [`Dns01Solver::cname_delegation`]: crate::Dns01Solver::cname_delegation
+#[derive(Debug, Clone)]
+pub struct CnameDelegation {
+  lookup: Lookup,
+}
+
+#[derive(Debug, Clone)]
+enum Lookup {
+  Static(HashMap<String, String>),
+  #[cfg(any(feature = "rfc2136", feature = "dns-propagation"))]
+  Live {
+    resolvers: Vec<SocketAddr>,
+    query_timeout: Duration,
+  },
+}
+
+impl CnameDelegation {
+  /// Look up CNAME records through the given recursive resolvers. If no
+  /// resolvers are given, the ones in `/etc/resolv.conf` are used.
+  #[cfg(any(feature = "rfc2136", feature = "dns-propagation"))]
+  pub fn live(resolvers: Vec<SocketAddr>) -> Self {
+    CnameDelegation {
+      lookup: Lookup::Live {
+        resolvers,
+        query_timeout: Duration::from_secs(5),
+      },
+    }
+  }
+
+  /// Use a static map from record names to CNAME targets instead of DNS
+  /// lookups. Names that are not in the map are not delegated.
+  pub fn static_map(delegations: HashMap<String, String>) -> Self {
+    CnameDelegation {
+      lookup: Lookup::Static(
+        delegations
+          .into_iter()
+          .map(|(name, target)| {
+            (normalize_name(&name), normalize_name(&target))
+          })
+          .collect(),
+      ),
+    }
+  }
+
+  /// Follow the CNAME chain starting at `name`, and return the name at its
+  /// end. Names without a CNAME record are returned as is, without a
+  /// trailing dot.
+  ///
+  /// Returns [`Error::CnameLoop`] if the chain contains a loop or is longer
+  /// than 16 records.
+  #[instrument(level = Level::DEBUG, name = "acme2::CnameDelegation::resolve", err, skip(self))]
+  pub async fn resolve(&self, name: &str) -> Result<String, Error> {
+    let mut current = normalize_name(name);
+    let mut seen = HashSet::new();
+    while let Some(target) = self.cname(&current).await? {
+      debug!({ name = %current, target = %target }, "Following CNAME.");
+      seen.insert(current);
+      if seen.contains(&target) || seen.len() >= MAX_CHAIN_LENGTH {
+        return Err(Error::CnameLoop(normalize_name(name)));
+      }
+      current = target;
+    }
+    Ok(current)
+  }
+
+  /// The target of the CNAME record at `name`, if there is one.
+  async fn cname(&self, name: &str) -> Result<Option<String>, Error> {
+    match &self.lookup {
+      Lookup::Static(delegations) => Ok(delegations.get(name).cloned()),
+      #[cfg(any(feature = "rfc2136", feature = "dns-propagation"))]
+      Lookup::Live {
+        resolvers,
+        query_timeout,
+      } => {
+        let response =
+          resolve(resolvers, name, TYPE_CNAME, *query_timeout).await?;
+        Ok(
+          response
+            .answers
+            .into_iter()
+            .find_map(|record| match record.data {
+              RecordData::Cname(target) if record.name == name => Some(target),
+              _ => None,
+            }),
+        )
+      }
+    }
+  }
+}
@@ -0,0 +1,275 @@
+use crate::dns_wire::*;
+use crate::error::*;
+use crate::helpers::normalize_name;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::debug;
+use tracing::instrument;
+use tracing::Level;
+
+/// A check that waits until a DNS01 challenge record is served by all
+/// authoritative nameservers of its zone, before the ACME server is asked to
+/// validate the challenge.
+///
+/// The authoritative nameservers are discovered through recursive resolvers
+/// (by default the ones in `/etc/resolv.conf`), and are then queried
+/// directly, so caching resolvers do not delay the check. A nameserver with
+/// several addresses (for example IPv4 and IPv6) counts as propagated once
+/// any of its addresses serves the record.
+#[derive(Debug, Clone)]
+pub struct DnsPropagationCheck {
+  resolvers: Vec<SocketAddr>,
+  nameservers: Vec<SocketAddr>,
+  timeout: Duration,
+  poll_interval: Duration,
+  query_timeout: Duration,
+}
+
+/// An authoritative nameserver, with all of its addresses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Nameserver {
+  pub(crate) host: String,
+  pub(crate) addrs: Vec<SocketAddr>,
+}
+
+impl Default for DnsPropagationCheck {
+  fn default() -> Self {
+    DnsPropagationCheck {
+      resolvers: vec![],
+      nameservers: vec![],
+      timeout: Duration::from_secs(120),
+      poll_interval: Duration::from_secs(5),
+      query_timeout: Duration::from_secs(5),
+    }
+  }
+}
+
+impl DnsPropagationCheck {
+  /// Create a propagation check with the default settings.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// The recursive resolvers used to discover the authoritative
+  /// nameservers. Defaults to the nameservers in `/etc/resolv.conf`.
+  pub fn resolvers(&mut self, resolvers: Vec<SocketAddr>) -> &mut Self {
+    self.resolvers = resolvers;
+    self
+  }
+
+  /// Query these nameservers instead of discovering the authoritative
+  /// nameservers of the zone. Useful for hidden primaries or split horizon
+  /// setups.
+  pub fn nameservers(&mut self, nameservers: Vec<SocketAddr>) -> &mut Self {
+    self.nameservers = nameservers;
+    self
+  }
+
+  /// How long to wait for the record to propagate in total. Defaults to 2
+  /// minutes.
+  pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// How long to wait between polling the nameservers. Defaults to 5
+  /// seconds.
+  pub fn poll_interval(&mut self, poll_interval: Duration) -> &mut Self {
+    self.poll_interval = poll_interval;
+    self
+  }
+
+  /// How long to wait for a single DNS response. Defaults to 5 seconds.
+  pub fn query_timeout(&mut self, query_timeout: Duration) -> &mut Self {
+    self.query_timeout = query_timeout;
+    self
+  }
+
+  /// Wait until all authoritative nameservers serve a TXT record `name`
+  /// containing `value`.
+  ///
+  /// Returns [`Error::DnsPropagationTimeout`] if this does not happen
+  /// within the timeout, which includes discovering the nameservers.
+  #[instrument(level = Level::INFO, name = "acme2::DnsPropagationCheck::wait", err, skip(self))]
+  pub async fn wait(&self, name: &str, value: &str) -> Result<(), Error> {
+    let deadline = Instant::now() + self.timeout;
+    let nameservers = if self.nameservers.is_empty() {
+      tokio::time::timeout_at(deadline, self.authoritative_nameservers(name))
+        .await
+        .map_err(|_| Error::DnsPropagationTimeout(normalize_name(name)))??
+    } else {
+      self
+        .nameservers
+        .iter()
+        .map(|addr| Nameserver {
+          host: addr.to_string(),
+          addrs: vec![*addr],
+        })
+        .collect()
+    };
+    self.wait_for(nameservers, name, value, deadline).await
+  }
+
+  /// Poll the nameservers until all of them serve the record.
+  pub(crate) async fn wait_for(
+    &self,
+    nameservers: Vec<Nameserver>,
+    name: &str,
+    value: &str,
+    deadline: Instant,
+  ) -> Result<(), Error> {
+    let mut pending = nameservers;
+    debug!({ nameservers = ?pending }, "Checking DNS propagation.");
+
+    loop {
+      let mut still_pending = vec![];
+      for nameserver in pending {
+        let mut propagated = false;
+        for addr in &nameserver.addrs {
+          if self.has_txt_record(*addr, name, value).await {
+            propagated = true;
+            break;
+          }
+        }
+        if !propagated {
+          still_pending.push(nameserver);
+        }
+      }
+      pending = still_pending;
+      if pending.is_empty() {
+        debug!("Record has propagated to all nameservers.");
+        return Ok(());
+      }
+      if Instant::now() + self.poll_interval > deadline {
+        return Err(Error::DnsPropagationTimeout(normalize_name(name)));
+      }
+      debug!(
+        { pending = ?pending, delay = ?self.poll_interval },
+        "Record has not propagated yet. Waiting to poll."
+      );
+      tokio::time::sleep(self.poll_interval).await;
+    }
+  }
+
+  /// If the nameserver serves a TXT record `name` containing `value`.
+  /// Failures to reach the nameserver are treated as the record not being
+  /// served yet.
+  async fn has_txt_record(
+    &self,
+    nameserver: SocketAddr,
+    name: &str,
+    value: &str,
+  ) -> bool {
+    match self.query(nameserver, name, TYPE_TXT, false).await {
+      Ok(response) => response
+        .answers
+        .iter()
+        .any(|record| record.data == RecordData::Txt(value.to_string())),
+      Err(err) => {
+        debug!(
+          { nameserver = %nameserver, error = %err },
+          "Failed to query nameserver."
+        );
+        false
+      }
+    }
+  }
+
+  async fn query(
+    &self,
+    server: SocketAddr,
+    name: &str,
+    r#type: u16,
+    recursion_desired: bool,
+  ) -> Result<Response, Error> {
+    query(server, name, r#type, recursion_desired, self.query_timeout).await
+  }
+
+  async fn resolve(&self, name: &str, r#type: u16) -> Result<Response, Error> {
+    resolve(&self.resolvers, name, r#type, self.query_timeout).await
+  }
+
+  /// The authoritative nameservers of the zone that contains `name`.
+  /// Nameservers whose addresses can not be resolved are skipped.
+  pub(crate) async fn authoritative_nameservers(
+    &self,
+    name: &str,
+  ) -> Result<Vec<Nameserver>, Error> {
+    let zone = self.find_zone(name).await?;
+    debug!({ zone = %zone }, "Found zone of record.");
+
+    let response = self.resolve(&zone, TYPE_NS).await?;
+    let mut nameservers = vec![];
+    for record in response.answers {
+      let host = match record.data {
+        RecordData::Ns(host) if record.name == zone => host,
+        _ => continue,
+      };
+      let mut addrs = vec![];
+      for r#type in [TYPE_A, TYPE_AAAA].iter() {
+        let response = match self.resolve(&host, *r#type).await {
+          Ok(response) => response,
+          Err(err) => {
+            debug!(
+              { host = %host, r#type = *r#type, error = %err },
+              "Failed to resolve nameserver."
+            );
+            continue;
+          }
+        };
+        for record in response.answers {
+          let ip: IpAddr = match record.data {
+            RecordData::A(ip) => ip.into(),
+            RecordData::Aaaa(ip) => ip.into(),
+            _ => continue,
+          };
+          let addr = SocketAddr::new(ip, 53);
+          if !addrs.contains(&addr) {
+            addrs.push(addr);
+          }
+        }
+      }
+      if addrs.is_empty() {
+        debug!({ host = %host }, "Skipping nameserver without addresses.");
+        continue;
+      }
+      if !nameservers.iter().any(|ns: &Nameserver| ns.host == host) {
+        nameservers.push(Nameserver { host, addrs });
+      }
+    }
+
+    if nameservers.is_empty() {
+      return Err(Error::Validation(
+        "could not find the authoritative nameservers of the zone",
+      ));
+    }
+    Ok(nameservers)
+  }
+
+  /// Find the apex of the zone that contains `name`, by looking for the SOA
+  /// record that is returned for the name or one of its parents.
+  async fn find_zone(&self, name: &str) -> Result<String, Error> {
+    let mut candidate = normalize_name(name);
+    loop {
+      let response = self.resolve(&candidate, TYPE_SOA).await?;
+      let soa = response
+        .answers
+        .iter()
+        .chain(response.authorities.iter())
+        .find(|record| record.data == RecordData::Soa);
+      if let Some(soa) = soa {
+        return Ok(soa.name.clone());
+      }
+      candidate = match candidate.split_once('.') {
+        Some((_, parent)) => parent.to_string(),
+        None => {
+          return Err(Error::Validation(
+            "could not find the zone of the DNS record",
+          ))
+        }
+      };
+    }
+  }
+}
@@ -0,0 +1,416 @@
+use crate::error::*;
+use crate::helpers::normalize_name;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::net::UdpSocket;
+use tracing::debug;
+
+pub(crate) const TYPE_A: u16 = 1;
+pub(crate) const TYPE_NS: u16 = 2;
+pub(crate) const TYPE_CNAME: u16 = 5;
+pub(crate) const TYPE_SOA: u16 = 6;
+pub(crate) const TYPE_TXT: u16 = 16;
+pub(crate) const TYPE_AAAA: u16 = 28;
+#[cfg(feature = "rfc2136")]
+pub(crate) const TYPE_TSIG: u16 = 250;
+
+pub(crate) const CLASS_IN: u16 = 1;
+#[cfg(feature = "rfc2136")]
+pub(crate) const CLASS_NONE: u16 = 254;
+#[cfg(feature = "rfc2136")]
+pub(crate) const CLASS_ANY: u16 = 255;
+
+pub(crate) const FLAG_QR: u16 = 0x8000;
+pub(crate) const FLAG_TC: u16 = 0x0200;
+pub(crate) const FLAG_RD: u16 = 0x0100;
+
+pub(crate) const RCODE_NOERROR: u16 = 0;
+pub(crate) const RCODE_NXDOMAIN: u16 = 3;
+
+/// A random message ID.
+pub(crate) fn random_id() -> Result<u16, Error> {
+  let mut id = [0; 2];
+  openssl::rand::rand_bytes(&mut id)?;
+  Ok(u16::from_be_bytes(id))
+}
+
+/// Encode a domain name in uncompressed wire format.
+pub(crate) fn encode_name(buf: &mut Vec<u8>, name: &str) -> Result<(), Error> {
+  let name = normalize_name(name);
+  let start = buf.len();
+  if !name.is_empty() {
+    for label in name.split('.') {
+      if label.is_empty() || label.len() > 63 {
+        return Err(Error::Validation("invalid label in domain name"));
+      }
+      buf.push(label.len() as u8);
+      buf.extend_from_slice(label.as_bytes());
+    }
+  }
+  buf.push(0);
+  if buf.len() - start > 255 {
+    return Err(Error::Validation("domain name is too long"));
+  }
+  Ok(())
+}
+
+/// Encode TXT record data as a sequence of character strings of at most 255
+/// bytes each.
+#[cfg(any(feature = "rfc2136", test))]
+pub(crate) fn encode_txt(value: &str) -> Vec<u8> {
+  let mut rdata = vec![];
+  for chunk in value.as_bytes().chunks(255) {
+    rdata.push(chunk.len() as u8);
+    rdata.extend_from_slice(chunk);
+  }
+  if rdata.is_empty() {
+    rdata.push(0);
+  }
+  rdata
+}
+
+/// Encode a resource record.
+#[cfg(any(feature = "rfc2136", test))]
+pub(crate) fn encode_record(
+  buf: &mut Vec<u8>,
+  name: &str,
+  r#type: u16,
+  class: u16,
+  ttl: u32,
+  rdata: &[u8],
+) -> Result<(), Error> {
+  encode_name(buf, name)?;
+  buf.extend_from_slice(&r#type.to_be_bytes());
+  buf.extend_from_slice(&class.to_be_bytes());
+  buf.extend_from_slice(&ttl.to_be_bytes());
+  buf.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+  buf.extend_from_slice(rdata);
+  Ok(())
+}
+
+/// Encode a query for a single question.
+pub(crate) fn encode_query(
+  id: u16,
+  name: &str,
+  r#type: u16,
+  recursion_desired: bool,
+) -> Result<Vec<u8>, Error> {
+  let flags = if recursion_desired { FLAG_RD } else { 0 };
+  let mut msg = vec![];
+  msg.extend_from_slice(&id.to_be_bytes());
+  msg.extend_from_slice(&flags.to_be_bytes());
+  // QDCOUNT, ANCOUNT, NSCOUNT, ARCOUNT
+  for count in [1u16, 0, 0, 0].iter() {
+    msg.extend_from_slice(&count.to_be_bytes());
+  }
+  encode_name(&mut msg, name)?;
+  msg.extend_from_slice(&r#type.to_be_bytes());
+  msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+  Ok(msg)
+}
+
+/// A cursor over a DNS message.
+pub(crate) struct Reader<'a> {
+  pub(crate) msg: &'a [u8],
+  pub(crate) pos: usize,
+}
+
+impl<'a> Reader<'a> {
+  pub(crate) fn new(msg: &'a [u8]) -> Self {
+    Reader { msg, pos: 0 }
+  }
+
+  pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+    let bytes = self
+      .msg
+      .get(self.pos..self.pos + len)
+      .ok_or_else(|| transport_err("malformed DNS message"))?;
+    self.pos += len;
+    Ok(bytes)
+  }
+
+  pub(crate) fn u8(&mut self) -> Result<u8, Error> {
+    Ok(self.bytes(1)?[0])
+  }
+
+  pub(crate) fn u16(&mut self) -> Result<u16, Error> {
+    let bytes = self.bytes(2)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+  }
+
+  #[cfg(feature = "rfc2136")]
+  pub(crate) fn u48(&mut self) -> Result<u64, Error> {
+    let mut buf = [0; 8];
+    buf[2..].copy_from_slice(self.bytes(6)?);
+    Ok(u64::from_be_bytes(buf))
+  }
+
+  /// Read a possibly compressed domain name, without a trailing dot.
+  pub(crate) fn name(&mut self) -> Result<String, Error> {
+    let mut labels: Vec<String> = vec![];
+    let mut pos = self.pos;
+    let mut end = None;
+    // Every pointer has to point backwards, so this bounds the number of
+    // jumps and prevents loops.
+    let mut limit = pos;
+    loop {
+      let len = *self
+        .msg
+        .get(pos)
+        .ok_or_else(|| transport_err("malformed DNS message"))?;
+      match len {
+        0 => {
+          end.get_or_insert(pos + 1);
+          break;
+        }
+        len if len & 0xc0 == 0xc0 => {
+          let low = *self
+            .msg
+            .get(pos + 1)
+            .ok_or_else(|| transport_err("malformed DNS message"))?;
+          let target = (((len & 0x3f) as usize) << 8) | low as usize;
+          if target >= limit {
+            return Err(transport_err("malformed DNS message"));
+          }
+          end.get_or_insert(pos + 2);
+          limit = target;
+          pos = target;
+        }
+        len => {
+          let label = self
+            .msg
+            .get(pos + 1..pos + 1 + len as usize)
+            .ok_or_else(|| transport_err("malformed DNS message"))?;
+          labels.push(String::from_utf8_lossy(label).to_lowercase());
+          pos += 1 + len as usize;
+        }
+      }
+    }
+    self.pos = end.unwrap();
+    Ok(labels.join("."))
+  }
+}
+
+/// The data of a resource record, for the record types that are used by
+/// this crate.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) enum RecordData {
+  A(Ipv4Addr),
+  Aaaa(Ipv6Addr),
+  Ns(String),
+  Cname(String),
+  Soa,
+  Txt(String),
+  Other,
+}
+
+/// A resource record in a response.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Record {
+  pub(crate) name: String,
+  pub(crate) data: RecordData,
+}
+
+/// A parsed response message.
+#[derive(Debug)]
+pub(crate) struct Response {
+  pub(crate) rcode: u16,
+  pub(crate) answers: Vec<Record>,
+  #[cfg(feature = "dns-propagation")]
+  pub(crate) authorities: Vec<Record>,
+}
+
+fn read_record(reader: &mut Reader) -> Result<Record, Error> {
+  let name = reader.name()?;
+  let r#type = reader.u16()?;
+  reader.bytes(6)?;
+  let rdlength = reader.u16()? as usize;
+  let end = reader.pos + rdlength;
+  let data = match r#type {
+    TYPE_A if rdlength == 4 => {
+      let b = reader.bytes(4)?;
+      RecordData::A(Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+    }
+    TYPE_AAAA if rdlength == 16 => {
+      let mut b = [0; 16];
+      b.copy_from_slice(reader.bytes(16)?);
+      RecordData::Aaaa(Ipv6Addr::from(b))
+    }
+    TYPE_NS => RecordData::Ns(reader.name()?),
+    TYPE_CNAME => RecordData::Cname(reader.name()?),
+    TYPE_SOA => RecordData::Soa,
+    TYPE_TXT => {
+      let mut value = vec![];
+      while reader.pos < end {
+        let len = reader.u8()? as usize;
+        value.extend_from_slice(reader.bytes(len)?);
+      }
+      RecordData::Txt(String::from_utf8_lossy(&value).into_owned())
+    }
+    _ => RecordData::Other,
+  };
+  if reader.pos > end || end > reader.msg.len() {
+    return Err(transport_err("malformed DNS message"));
+  }
+  reader.pos = end;
+  Ok(Record { name, data })
+}
+
+/// Parse a response to a query with the given ID.
+pub(crate) fn parse_response(msg: &[u8], id: u16) -> Result<Response, Error> {
+  let mut reader = Reader::new(msg);
+  if reader.u16()? != id {
+    return Err(transport_err("DNS response ID does not match the request"));
+  }
+  let flags = reader.u16()?;
+  if flags & FLAG_QR == 0 {
+    return Err(transport_err("DNS message is not a response"));
+  }
+  let counts = [reader.u16()?, reader.u16()?, reader.u16()?, reader.u16()?];
+  for _ in 0..counts[0] {
+    reader.name()?;
+    reader.bytes(4)?;
+  }
+  let mut answers = vec![];
+  for _ in 0..counts[1] {
+    answers.push(read_record(&mut reader)?);
+  }
+  #[cfg(feature = "dns-propagation")]
+  let mut authorities = vec![];
+  #[cfg(feature = "dns-propagation")]
+  for _ in 0..counts[2] {
+    authorities.push(read_record(&mut reader)?);
+  }
+
+  Ok(Response {
+    rcode: flags & 0xf,
+    answers,
+    #[cfg(feature = "dns-propagation")]
+    authorities,
+  })
+}
+
+/// Send a message to a DNS server over UDP, and retry over TCP if the
+/// response was truncated.
+pub(crate) async fn exchange(
+  server: SocketAddr,
+  msg: &[u8],
+  timeout: Duration,
+) -> Result<Vec<u8>, Error> {
+  let id = &msg[..2];
+  let response = tokio::time::timeout(timeout, async {
+    let bind_addr: SocketAddr = if server.is_ipv4() {
+      ([0, 0, 0, 0], 0).into()
+    } else {
+      ([0u16; 8], 0).into()
+    };
+    let socket = map_transport_err(UdpSocket::bind(bind_addr).await)?;
+    map_transport_err(socket.connect(server).await)?;
+    map_transport_err(socket.send(msg).await)?;
+
+    let mut buf = vec![0; 4096];
+    loop {
+      let len = map_transport_err(socket.recv(&mut buf).await)?;
+      // Ignore stray datagrams that are not a response to our message.
+      if len >= 12 && &buf[..2] == id {
+        buf.truncate(len);
+        return Ok::<_, Error>(buf);
+      }
+    }
+  })
+  .await
+  .map_err(|_| transport_err("timed out waiting for the DNS server"))??;
+
+  let flags = u16::from_be_bytes([response[2], response[3]]);
+  if flags & FLAG_TC == 0 {
+    return Ok(response);
+  }
+
+  debug!("UDP response was truncated, retrying over TCP.");
+  tokio::time::timeout(timeout, async {
+    let mut stream = map_transport_err(TcpStream::connect(server).await)?;
+    let mut framed = (msg.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(msg);
+    map_transport_err(stream.write_all(&framed).await)?;
+
+    let mut len = [0; 2];
+    map_transport_err(stream.read_exact(&mut len).await)?;
+    let mut buf = vec![0; u16::from_be_bytes(len) as usize];
+    map_transport_err(stream.read_exact(&mut buf).await)?;
+    Ok(buf)
+  })
+  .await
+  .map_err(|_| transport_err("timed out waiting for the DNS server"))?
+}
+
+/// Send a query for a single question to a DNS server.
+pub(crate) async fn query(
+  server: SocketAddr,
+  name: &str,
+  r#type: u16,
+  recursion_desired: bool,
+  timeout: Duration,
+) -> Result<Response, Error> {
+  let id = random_id()?;
+  let query = encode_query(id, name, r#type, recursion_desired)?;
+  let response = exchange(server, &query, timeout).await?;
+  parse_response(&response, id)
+}
+
+/// Query the recursive resolvers in order, until one of them answers. If no
+/// resolvers are given, the ones in `/etc/resolv.conf` are used.
+pub(crate) async fn resolve(
+  resolvers: &[SocketAddr],
+  name: &str,
+  r#type: u16,
+  timeout: Duration,
+) -> Result<Response, Error> {
+  let resolvers = if resolvers.is_empty() {
+    system_resolvers().await
+  } else {
+    resolvers.to_vec()
+  };
+  if resolvers.is_empty() {
+    return Err(Error::Validation("no DNS resolvers are configured"));
+  }
+
+  let mut last_err = None;
+  for resolver in resolvers {
+    match query(resolver, name, r#type, true, timeout).await {
+      Ok(response)
+        if response.rcode == RCODE_NOERROR
+          || response.rcode == RCODE_NXDOMAIN =>
+      {
+        return Ok(response)
+      }
+      Ok(_) => {
+        last_err = Some(transport_err("the DNS resolver failed to answer"))
+      }
+      Err(err) => last_err = Some(err),
+    }
+  }
+  Err(last_err.unwrap())
+}
+
+/// The nameservers configured in `/etc/resolv.conf`.
+async fn system_resolvers() -> Vec<SocketAddr> {
+  let conf = tokio::fs::read_to_string("/etc/resolv.conf")
+    .await
+    .unwrap_or_default();
+  conf
+    .lines()
+    .filter_map(|line| {
+      let mut parts = line.split_whitespace();
+      match (parts.next(), parts.next()) {
+        (Some("nameserver"), Some(ip)) => ip.parse().ok(),
+        _ => None,
+      }
+    })
+    .map(|ip| SocketAddr::new(ip, 53))
+    .collect()
+}
@@ -11,6 +11,33 @@ pub enum Error {
   #[error(transparent)]
   Server(#[from] ServerError),
 
+  /// The ACME server rejected the `notBefore` / `notAfter` requested through
+  /// [`crate::OrderBuilder::not_before`] or
+  /// [`crate::OrderBuilder::not_after`].
+  ///
+  /// ACME has no dedicated error type for this, so this is only returned for
+  /// `malformed` errors whose detail names `notBefore` or `notAfter`. All
+  /// other rejections of the order are returned as [`Error::Server`].
+  #[error("the ACME server rejected the requested certificate validity: {0}")]
+  ValidityRejected(ServerError),
+
+  #[error("the DNS record {0} did not propagate to all authoritative nameservers in time")]
+  DnsPropagationTimeout(String),
+
+  #[error("the ACME server did not offer an acceptable challenge for {identifier} (offered: {})", offered.join(", "))]
+  NoAcceptableChallenge {
+    /// The identifier of the authorization.
+    identifier: String,
+    /// The challenge types the ACME server offered.
+    offered: Vec<String>,
+  },
+
+  #[error("the CNAME chain of {0} contains a loop or is too long")]
+  CnameLoop(String),
+
+  #[error("the http-01 self check failed: {0}")]
+  Http01SelfCheckFailed(String),
+
   #[error(transparent)]
   Transport(Box<dyn std::error::Error + Send + Sync>),
 
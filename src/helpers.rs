@@ -6,11 +6,17 @@ use openssl::pkey::PKey;
 use openssl::pkey::Private;
 use openssl::rsa::Rsa;
 use serde::Deserialize;
+use serde::Deserializer;
 use serde::Serialize;
+use std::net::IpAddr;
+use std::time::Duration;
+use std::time::SystemTime;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
 
 /// This is a identifier for a resource that the ACME server
-/// can provision certificates for (a domain).
-#[derive(Deserialize, Serialize, Debug)]
+/// can provision certificates for (a domain or an IP address).
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Identifier {
   /// The type of identifier.
@@ -19,6 +25,196 @@ pub struct Identifier {
   pub value: String,
 }
 
+impl Identifier {
+  /// Create a type `dns` identifier for a domain name.
+  pub fn dns(fqdn: String) -> Self {
+    Identifier {
+      r#type: "dns".to_string(),
+      value: fqdn,
+    }
+  }
+
+  /// Create a type `ip` identifier for an IP address (RFC 8738).
+  pub fn ip(ip: IpAddr) -> Self {
+    Identifier {
+      r#type: "ip".to_string(),
+      value: ip.to_string(),
+    }
+  }
+
+  /// If this is a type `dns` identifier.
+  pub fn is_dns(&self) -> bool {
+    self.r#type == "dns"
+  }
+
+  /// If this is a type `ip` identifier.
+  pub fn is_ip(&self) -> bool {
+    self.r#type == "ip"
+  }
+
+  /// The IP address of a type `ip` identifier. Returns `None` for other
+  /// identifier types, or if the value is not a valid IP address.
+  pub fn ip_addr(&self) -> Option<IpAddr> {
+    if self.is_ip() {
+      self.value.parse().ok()
+    } else {
+      None
+    }
+  }
+
+  /// The host used to reach this identifier in a `http-01` challenge URL.
+  /// IPv6 addresses are enclosed in brackets.
+  pub fn http_host(&self) -> String {
+    match self.ip_addr() {
+      Some(IpAddr::V6(ip)) => format!("[{}]", ip),
+      Some(IpAddr::V4(ip)) => ip.to_string(),
+      None => self.value.clone(),
+    }
+  }
+
+  /// The name the ACME server sends as the TLS server name (SNI) when
+  /// validating this identifier with a `tls-alpn-01` challenge.
+  ///
+  /// This is the domain name itself for `dns` identifiers, and the reverse
+  /// DNS name (`in-addr.arpa` / `ip6.arpa`) of the address for `ip`
+  /// identifiers (RFC 8738 section 6).
+  pub fn tls_server_name(&self) -> String {
+    match self.ip_addr() {
+      Some(ip) => reverse_dns_name(ip),
+      None => self.value.clone(),
+    }
+  }
+
+  /// The name of the TXT record that is checked when validating this
+  /// identifier with a `dns-01` challenge, without a trailing dot.
+  ///
+  /// Returns `None` for `ip` identifiers, as these can not be validated
+  /// with `dns-01` challenges.
+  pub fn dns01_record_name(&self) -> Option<String> {
+    if self.is_ip() {
+      return None;
+    }
+    let domain = self.value.trim_start_matches("*.").trim_end_matches('.');
+    Some(format!("_acme-challenge.{}", domain))
+  }
+}
+
+/// The reverse DNS name of an IP address (RFC 1034 section 5.2.1 and RFC
+/// 3596 section 2.5).
+pub(crate) fn reverse_dns_name(ip: IpAddr) -> String {
+  match ip {
+    IpAddr::V4(ip) => {
+      let [a, b, c, d] = ip.octets();
+      format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+    }
+    IpAddr::V6(ip) => {
+      let mut name = String::new();
+      for byte in ip.octets().iter().rev() {
+        name.push_str(&format!("{:x}.{:x}.", byte & 0xf, byte >> 4));
+      }
+      name.push_str("ip6.arpa");
+      name
+    }
+  }
+}
+
+/// Extract all URLs with the given relation type from the `Link` headers
+/// of a response (RFC 8288). Relative URLs are resolved against `base`.
+pub(crate) fn extract_links(
+  headers: &reqwest::header::HeaderMap,
+  base: &str,
+  rel: &str,
+) -> Vec<String> {
+  let base = reqwest::Url::parse(base).ok();
+  let mut links = vec![];
+
+  for value in headers.get_all(reqwest::header::LINK) {
+    let mut rest = match value.to_str() {
+      Ok(value) => value,
+      Err(_) => continue,
+    };
+
+    while let Some(start) = rest.find('<') {
+      let end = match rest[start..].find('>') {
+        Some(end) => start + end,
+        None => break,
+      };
+      let url = &rest[start + 1..end];
+      rest = &rest[end + 1..];
+
+      let params_end = rest.find(',').unwrap_or(rest.len());
+      let params = &rest[..params_end];
+      rest = &rest[params_end..];
+
+      let matches = params.split(';').any(|param| {
+        let mut parts = param.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("").trim().trim_matches('"');
+        name.eq_ignore_ascii_case("rel")
+          && value
+            .split_whitespace()
+            .any(|r| r.eq_ignore_ascii_case(rel))
+      });
+      if !matches {
+        continue;
+      }
+
+      let url = match &base {
+        Some(base) => base
+          .join(url)
+          .map(|url| url.to_string())
+          .unwrap_or_else(|_| url.to_string()),
+        None => url.to_string(),
+      };
+      links.push(url);
+    }
+  }
+
+  links
+}
+
+/// Extract the delay from the `Retry-After` header of a response. The header
+/// can either contain a number of seconds, or a HTTP date.
+pub(crate) fn extract_retry_after(
+  headers: &reqwest::header::HeaderMap,
+) -> Option<Duration> {
+  let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+  let value = value.trim();
+
+  if let Ok(seconds) = value.parse::<u64>() {
+    return Some(Duration::from_secs(seconds));
+  }
+
+  let date = httpdate::parse_http_date(value).ok()?;
+  Some(
+    date
+      .duration_since(SystemTime::now())
+      .unwrap_or_else(|_| Duration::from_secs(0)),
+  )
+}
+
+/// Format a timestamp as an RFC 3339 date, as used in ACME resources.
+pub(crate) fn format_rfc3339(timestamp: SystemTime) -> Result<String, Error> {
+  OffsetDateTime::from(timestamp)
+    .format(&Rfc3339)
+    .map_err(|err| Error::Other(Box::new(err)))
+}
+
+/// Deserialize an RFC 3339 date, as used in ACME resources.
+pub(crate) fn deserialize_rfc3339<'de, D: Deserializer<'de>>(
+  deserializer: D,
+) -> Result<SystemTime, D::Error> {
+  let value = String::deserialize(deserializer)?;
+  OffsetDateTime::parse(&value, &Rfc3339)
+    .map(SystemTime::from)
+    .map_err(serde::de::Error::custom)
+}
+
+/// Lowercase a domain name and remove the trailing dot.
+pub(crate) fn normalize_name(name: &str) -> String {
+  name.trim_end_matches('.').to_lowercase()
+}
+
 pub(crate) fn b64(data: &[u8]) -> String {
   base64::encode_config(data, ::base64::URL_SAFE_NO_PAD)
 }
@@ -38,3 +234,17 @@ pub fn gen_ec_p256_private_key() -> Result<PKey<Private>, Error> {
   let key = PKey::from_ec_key(rsa)?;
   Ok(key)
 }
+
+/// Generate a new P384 EC private key using the system random.
+pub fn gen_ec_p384_private_key() -> Result<PKey<Private>, Error> {
+  let group = EcGroup::from_curve_name(Nid::SECP384R1).unwrap();
+  let ec = EcKey::generate(&group)?;
+  let key = PKey::from_ec_key(ec)?;
+  Ok(key)
+}
+
+/// Generate a new Ed25519 private key using the system random.
+pub fn gen_ed25519_private_key() -> Result<PKey<Private>, Error> {
+  let key = PKey::generate_ed25519()?;
+  Ok(key)
+}
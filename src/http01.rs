@@ -0,0 +1,195 @@
+use crate::authorization::Challenge;
+use crate::authorization::ACME_CHALLENGE_PATH_PREFIX;
+use crate::error::*;
+use hyper::header;
+use hyper::service::make_service_fn;
+use hyper::service::Service;
+use hyper::Body;
+use hyper::Method;
+use hyper::Request;
+use hyper::Response;
+use hyper::Server;
+use hyper::StatusCode;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::task::Context;
+use std::task::Poll;
+use tracing::debug;
+use tracing::instrument;
+use tracing::Level;
+
+/// A responder for HTTP01 challenges.
+///
+/// The responder holds a token store that [`Challenge`]s can be registered
+/// into and removed from. It is cheap to clone, and all clones share the same
+/// token store, so challenges can be registered while the responder is
+/// serving requests.
+///
+/// The responder can either serve requests on its own (see
+/// [`Http01Responder::serve`]), or wrap an existing hyper service so that
+/// only challenge requests are intercepted (see [`Http01Responder::wrap`]).
+#[derive(Clone, Debug, Default)]
+pub struct Http01Responder {
+  tokens: Arc<RwLock<HashMap<String, String>>>,
+}
+
+impl Http01Responder {
+  /// Create a new responder with an empty token store.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Register a HTTP01 challenge, so that its key authorization is served
+  /// for the challenge token.
+  pub fn register(&self, challenge: &Challenge) -> Result<(), Error> {
+    if challenge.r#type != "http-01" {
+      return Err(Error::Validation(
+        "only http-01 challenges can be registered with the http-01 responder",
+      ));
+    }
+    let token = challenge
+      .token
+      .clone()
+      .ok_or(Error::Validation("the challenge does not have a token"))?;
+    let key_authorization = challenge
+      .key_authorization()?
+      .ok_or(Error::Validation("the challenge does not have a token"))?;
+
+    self
+      .tokens
+      .write()
+      .unwrap()
+      .insert(token, key_authorization);
+    Ok(())
+  }
+
+  /// Remove a previously registered challenge from the token store.
+  pub fn remove(&self, challenge: &Challenge) {
+    if let Some(token) = &challenge.token {
+      self.tokens.write().unwrap().remove(token);
+    }
+  }
+
+  /// The key authorization served for a token, if it is registered.
+  pub fn key_authorization(&self, token: &str) -> Option<String> {
+    self.tokens.read().unwrap().get(token).cloned()
+  }
+
+  /// A hyper service that answers challenge requests, and responds with a
+  /// 404 to all other requests.
+  pub fn service(&self) -> Http01Service<NotFound> {
+    self.wrap(NotFound)
+  }
+
+  /// A hyper service that answers challenge requests, and forwards all other
+  /// requests to `inner`.
+  pub fn wrap<S>(&self, inner: S) -> Http01Service<S> {
+    Http01Service {
+      tokens: self.tokens.clone(),
+      inner,
+    }
+  }
+
+  /// Serve challenge requests on the given address.
+  ///
+  /// The ACME server will always send HTTP01 validation requests to port 80.
+  /// The returned future only resolves if the server fails.
+  #[instrument(level = Level::INFO, name = "acme2::Http01Responder::serve", err, skip(self))]
+  pub async fn serve(&self, addr: SocketAddr) -> Result<(), Error> {
+    let responder = self.clone();
+    let make_service = make_service_fn(move |_| {
+      let service = responder.service();
+      async move { Ok::<_, Infallible>(service) }
+    });
+
+    let server = Server::try_bind(&addr)
+      .map_err(|err| Error::Other(Box::new(err)))?
+      .serve(make_service);
+    debug!({ addr = %server.local_addr() }, "Serving http-01 challenges.");
+    server.await.map_err(|err| Error::Other(Box::new(err)))
+  }
+}
+
+/// A hyper service that answers HTTP01 challenge requests for the tokens
+/// registered in a [`Http01Responder`], and forwards all other requests to
+/// an inner service.
+#[derive(Clone, Debug)]
+pub struct Http01Service<S> {
+  tokens: Arc<RwLock<HashMap<String, String>>>,
+  inner: S,
+}
+
+impl<S> Http01Service<S> {
+  fn respond(&self, req: &Request<Body>) -> Option<Response<Body>> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+      return None;
+    }
+    let token = req.uri().path().strip_prefix(ACME_CHALLENGE_PATH_PREFIX)?;
+    let key_authorization = self.tokens.read().unwrap().get(token).cloned()?;
+
+    debug!({ token }, "Answering http-01 challenge request.");
+    let body = if req.method() == Method::HEAD {
+      Body::empty()
+    } else {
+      Body::from(key_authorization.clone())
+    };
+    let res = Response::builder()
+      .header(header::CONTENT_TYPE, "application/octet-stream")
+      .header(header::CONTENT_LENGTH, key_authorization.len())
+      .body(body)
+      .unwrap();
+    Some(res)
+  }
+}
+
+impl<S> Service<Request<Body>> for Http01Service<S>
+where
+  S: Service<Request<Body>, Response = Response<Body>>,
+  S::Future: Send + 'static,
+{
+  type Response = Response<Body>;
+  type Error = S::Error;
+  type Future =
+    Pin<Box<dyn Future<Output = Result<Response<Body>, S::Error>> + Send>>;
+
+  fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+    self.inner.poll_ready(cx)
+  }
+
+  fn call(&mut self, req: Request<Body>) -> Self::Future {
+    match self.respond(&req) {
+      Some(res) => Box::pin(async move { Ok(res) }),
+      None => Box::pin(self.inner.call(req)),
+    }
+  }
+}
+
+/// A hyper service that responds with a 404 to every request.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NotFound;
+
+impl Service<Request<Body>> for NotFound {
+  type Response = Response<Body>;
+  type Error = Infallible;
+  type Future = std::future::Ready<Result<Response<Body>, Infallible>>;
+
+  fn poll_ready(
+    &mut self,
+    _cx: &mut Context<'_>,
+  ) -> Poll<Result<(), Infallible>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn call(&mut self, _req: Request<Body>) -> Self::Future {
+    let res = Response::builder()
+      .status(StatusCode::NOT_FOUND)
+      .body(Body::empty())
+      .unwrap();
+    std::future::ready(Ok(res))
+  }
+}
@@ -1,7 +1,16 @@
 use crate::error::*;
 use crate::helpers::*;
+use async_trait::async_trait;
+use openssl::bn::BigNum;
+use openssl::bn::BigNumContext;
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::hash;
 use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::HasPublic;
+use openssl::pkey::Id;
 use openssl::pkey::PKey;
+use openssl::pkey::PKeyRef;
 use openssl::pkey::Private;
 use openssl::sign::Signer;
 use serde::Deserialize;
@@ -10,7 +19,8 @@ use serde_json::json;
 
 #[derive(Serialize, Deserialize, Clone, Default)]
 struct JwsHeader {
-  nonce: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  nonce: Option<String>,
   alg: String,
   url: String,
   #[serde(skip_serializing_if = "Option::is_none")]
@@ -19,35 +29,253 @@ struct JwsHeader {
   jwk: Option<Jwk>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Default)]
-pub(crate) struct Jwk {
-  e: String,
-  kty: String,
-  n: String,
+/// The JSON Web Key representation of the public part of an account key.
+///
+/// The fields are declared in lexicographic order, so that the serialized
+/// form can be used directly to compute the JWK thumbprint (RFC 7638).
+#[derive(Serialize, Deserialize, Clone, Default, Debug, Eq, PartialEq)]
+pub struct Jwk {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) crv: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) e: Option<String>,
+  pub(crate) kty: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) n: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) x: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) y: Option<String>,
 }
 
 impl Jwk {
-  pub fn new(pkey: &PKey<Private>) -> Jwk {
-    Jwk {
-      e: b64(&pkey.rsa().unwrap().e().to_vec()),
-      kty: "RSA".to_string(),
-      n: b64(&pkey.rsa().unwrap().n().to_vec()),
+  /// Create the JWK for a RSA, P-256 / P-384 EC, or Ed25519 public key.
+  pub fn new<T: HasPublic>(pkey: &PKeyRef<T>) -> Result<Jwk, Error> {
+    match pkey.id() {
+      Id::RSA => {
+        let rsa = pkey.rsa()?;
+        Ok(Jwk {
+          e: Some(b64(&rsa.e().to_vec())),
+          kty: "RSA".to_string(),
+          n: Some(b64(&rsa.n().to_vec())),
+          ..Default::default()
+        })
+      }
+      Id::EC => {
+        let ec = pkey.ec_key()?;
+        let alg = JwsAlgorithm::from_key(pkey)?;
+        let size = alg.coordinate_size().unwrap();
+        let mut ctx = BigNumContext::new()?;
+        let mut x = BigNum::new()?;
+        let mut y = BigNum::new()?;
+        ec.public_key().affine_coordinates(
+          ec.group(),
+          &mut x,
+          &mut y,
+          &mut ctx,
+        )?;
+        Ok(Jwk {
+          crv: Some(alg.curve().unwrap().to_string()),
+          kty: "EC".to_string(),
+          x: Some(b64(&x.to_vec_padded(size)?)),
+          y: Some(b64(&y.to_vec_padded(size)?)),
+          ..Default::default()
+        })
+      }
+      Id::ED25519 => Ok(Jwk {
+        crv: Some("Ed25519".to_string()),
+        kty: "OKP".to_string(),
+        x: Some(b64(&pkey.raw_public_key()?)),
+        ..Default::default()
+      }),
+      _ => Err(Error::Validation("unsupported account key type")),
     }
   }
+
+  /// The base64url encoded SHA-256 JWK thumbprint (RFC 7638) of this key.
+  pub fn thumbprint(&self) -> Result<String, Error> {
+    Ok(b64(&hash(
+      MessageDigest::sha256(),
+      &serde_json::to_string(self)?.into_bytes(),
+    )?))
+  }
 }
 
-pub(crate) fn jws(
+/// The JWS signature algorithm used for an account key.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum JwsAlgorithm {
+  RS256,
+  ES256,
+  ES384,
+  EdDSA,
+}
+
+impl JwsAlgorithm {
+  /// Select the signature algorithm matching the type (and curve) of a key.
+  pub fn from_key<T: HasPublic>(
+    pkey: &PKeyRef<T>,
+  ) -> Result<JwsAlgorithm, Error> {
+    match pkey.id() {
+      Id::RSA => Ok(JwsAlgorithm::RS256),
+      Id::EC => match pkey.ec_key()?.group().curve_name() {
+        Some(Nid::X9_62_PRIME256V1) => Ok(JwsAlgorithm::ES256),
+        Some(Nid::SECP384R1) => Ok(JwsAlgorithm::ES384),
+        _ => Err(Error::Validation("unsupported EC account key curve")),
+      },
+      Id::ED25519 => Ok(JwsAlgorithm::EdDSA),
+      _ => Err(Error::Validation("unsupported account key type")),
+    }
+  }
+
+  /// The name of this algorithm, as used in the `alg` JWS header.
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      JwsAlgorithm::RS256 => "RS256",
+      JwsAlgorithm::ES256 => "ES256",
+      JwsAlgorithm::ES384 => "ES384",
+      JwsAlgorithm::EdDSA => "EdDSA",
+    }
+  }
+
+  /// The message digest used by this algorithm. EdDSA hashes the input
+  /// internally, so it has none.
+  pub(crate) fn digest(&self) -> Option<MessageDigest> {
+    match self {
+      JwsAlgorithm::RS256 | JwsAlgorithm::ES256 => {
+        Some(MessageDigest::sha256())
+      }
+      JwsAlgorithm::ES384 => Some(MessageDigest::sha384()),
+      JwsAlgorithm::EdDSA => None,
+    }
+  }
+
+  fn curve(&self) -> Option<&'static str> {
+    match self {
+      JwsAlgorithm::RS256 | JwsAlgorithm::EdDSA => None,
+      JwsAlgorithm::ES256 => Some("P-256"),
+      JwsAlgorithm::ES384 => Some("P-384"),
+    }
+  }
+
+  /// The size in bytes of a single EC coordinate (and of each of the R and S
+  /// signature halves).
+  pub(crate) fn coordinate_size(&self) -> Option<i32> {
+    match self {
+      JwsAlgorithm::RS256 | JwsAlgorithm::EdDSA => None,
+      JwsAlgorithm::ES256 => Some(32),
+      JwsAlgorithm::ES384 => Some(48),
+    }
+  }
+}
+
+/// The MAC algorithm used to sign an external account binding.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HmacAlgorithm {
+  HS256,
+  HS384,
+  HS512,
+}
+
+impl HmacAlgorithm {
+  /// The name of this algorithm, as used in the `alg` JWS header.
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      HmacAlgorithm::HS256 => "HS256",
+      HmacAlgorithm::HS384 => "HS384",
+      HmacAlgorithm::HS512 => "HS512",
+    }
+  }
+
+  fn digest(&self) -> MessageDigest {
+    match self {
+      HmacAlgorithm::HS256 => MessageDigest::sha256(),
+      HmacAlgorithm::HS384 => MessageDigest::sha384(),
+      HmacAlgorithm::HS512 => MessageDigest::sha512(),
+    }
+  }
+}
+
+/// A signer for an ACME account key.
+///
+/// The account key is used to sign all requests to the ACME server. The
+/// default implementation for [`PKey<Private>`] signs in process with
+/// OpenSSL, but this trait can be implemented to keep the account key in a
+/// HSM or KMS, and only hand out signatures.
+///
+/// Implementations use the [`async_trait`](https://docs.rs/async-trait)
+/// attribute macro.
+#[async_trait]
+pub trait AccountSigner: std::fmt::Debug + Send + Sync {
+  /// The JWS algorithm that this signer produces signatures for.
+  fn algorithm(&self) -> Result<JwsAlgorithm, Error>;
+
+  /// The public part of the account key, as a JWK.
+  fn public_jwk(&self) -> Result<Jwk, Error>;
+
+  /// Sign the JWS signing input (`<protected>.<payload>`).
+  ///
+  /// The signature must be encoded as required by JWS. For ECDSA this is
+  /// the fixed size R || S encoding (RFC 7518 section 3.4), not DER.
+  async fn sign(&self, data: &[u8]) -> Result<Vec<u8>, Error>;
+
+  /// The private key backing this signer, if it can be exported.
+  fn private_key(&self) -> Option<PKey<Private>> {
+    None
+  }
+}
+
+/// Signs in process using OpenSSL. ECDSA signatures are converted from the
+/// DER encoding that OpenSSL produces to the R || S encoding used by JWS.
+#[async_trait]
+impl AccountSigner for PKey<Private> {
+  fn algorithm(&self) -> Result<JwsAlgorithm, Error> {
+    JwsAlgorithm::from_key(self)
+  }
+
+  fn public_jwk(&self) -> Result<Jwk, Error> {
+    Jwk::new(self)
+  }
+
+  async fn sign(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let alg = JwsAlgorithm::from_key(self)?;
+    let signature = match alg.digest() {
+      Some(digest) => {
+        let mut signer = Signer::new(digest, self)?;
+        signer.update(data)?;
+        signer.sign_to_vec()?
+      }
+      None => {
+        let mut signer = Signer::new_without_digest(self)?;
+        signer.sign_oneshot_to_vec(data)?
+      }
+    };
+
+    match alg.coordinate_size() {
+      None => Ok(signature),
+      Some(size) => {
+        let sig = EcdsaSig::from_der(&signature)?;
+        let mut raw = sig.r().to_vec_padded(size)?;
+        raw.extend(sig.s().to_vec_padded(size)?);
+        Ok(raw)
+      }
+    }
+  }
+
+  fn private_key(&self) -> Option<PKey<Private>> {
+    Some(self.clone())
+  }
+}
+
+pub(crate) async fn jws(
   url: &str,
   nonce: String,
   payload: &str,
-  pkey: &PKey<Private>,
+  signer: &dyn AccountSigner,
   account_id: Option<String>,
 ) -> Result<String, Error> {
-  let payload_b64 = b64(&payload.as_bytes());
-
   let mut header = JwsHeader {
-    nonce,
-    alg: "RS256".into(),
+    nonce: Some(nonce),
+    alg: signer.algorithm()?.as_str().into(),
     url: url.to_string(),
     ..Default::default()
   };
@@ -55,21 +283,95 @@ pub(crate) fn jws(
   if let Some(kid) = account_id {
     header.kid = kid.into();
   } else {
-    header.jwk = Some(Jwk::new(&pkey));
+    header.jwk = Some(signer.public_jwk()?);
   }
 
+  Ok(serde_json::to_string(
+    &sign_jws(&header, payload, signer).await?,
+  )?)
+}
+
+async fn sign_jws(
+  header: &JwsHeader,
+  payload: &str,
+  signer: &dyn AccountSigner,
+) -> Result<serde_json::Value, Error> {
+  let payload_b64 = b64(payload.as_bytes());
+  let protected_b64 = b64(&serde_json::to_string(header)?.into_bytes());
+
+  let signature_b64 = b64(
+    &signer
+      .sign(&format!("{}.{}", protected_b64, payload_b64).into_bytes())
+      .await?,
+  );
+
+  Ok(json!({
+    "protected": protected_b64,
+    "payload": payload_b64,
+    "signature": signature_b64
+  }))
+}
+
+/// Create the inner JWS for an account key rollover (RFC 8555 section
+/// 7.3.5). It is signed by the new key, and carries the account URL and the
+/// old key, so that the server can verify that both keys agree to the change.
+pub(crate) async fn key_change_jws(
+  url: &str,
+  account_id: &str,
+  old_jwk: &Jwk,
+  new_signer: &dyn AccountSigner,
+) -> Result<serde_json::Value, Error> {
+  let header = JwsHeader {
+    alg: new_signer.algorithm()?.as_str().into(),
+    url: url.to_string(),
+    jwk: Some(new_signer.public_jwk()?),
+    ..Default::default()
+  };
+
+  let payload = serde_json::to_string(&json!({
+    "account": account_id,
+    "oldKey": old_jwk,
+  }))?;
+
+  sign_jws(&header, &payload, new_signer).await
+}
+
+#[derive(Serialize)]
+struct EabHeader<'a> {
+  alg: &'a str,
+  kid: &'a str,
+  url: &'a str,
+}
+
+/// Create the inner JWS for an external account binding (RFC 8555 section
+/// 7.3.4). This binds the account key (`jwk`) to the external account
+/// identified by `kid`, using the MAC key provided by the CA.
+pub(crate) fn eab_jws(
+  url: &str,
+  kid: &str,
+  hmac_key: &[u8],
+  alg: HmacAlgorithm,
+  jwk: &Jwk,
+) -> Result<serde_json::Value, Error> {
+  let header = EabHeader {
+    alg: alg.as_str(),
+    kid,
+    url,
+  };
   let protected_b64 = b64(&serde_json::to_string(&header)?.into_bytes());
+  let payload_b64 = b64(&serde_json::to_string(jwk)?.into_bytes());
 
   let signature_b64 = {
-    let mut signer = Signer::new(MessageDigest::sha256(), pkey)?;
+    let key = PKey::hmac(hmac_key)?;
+    let mut signer = Signer::new(alg.digest(), &key)?;
     signer
       .update(&format!("{}.{}", protected_b64, payload_b64).into_bytes())?;
     b64(&signer.sign_to_vec()?)
   };
 
-  Ok(serde_json::to_string(&json!({
+  Ok(json!({
     "protected": protected_b64,
     "payload": payload_b64,
     "signature": signature_b64
-  }))?)
+  }))
 }
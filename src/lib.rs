@@ -9,6 +9,20 @@
 //! - ACME v2 support, tested against Let's Encrypt and Pebble
 //! - Fully async, using `reqwest` / Tokio
 //! - Support for DNS01 and HTTP01 validation
+//! - Optional built-in HTTP01 challenge responder (`http01-responder` feature)
+//! - HTTP01 validation through the webroot of an existing web server
+//! - Optional self-check that HTTP01 challenges are reachable before validating
+//! - Optional TLS-ALPN01 challenge responder for OpenSSL servers
+//!   (`tls-alpn01-responder` feature)
+//! - DNS01 validation through any DNS provider, with a built-in RFC 2136 (DNS
+//!   UPDATE) provider (`rfc2136` feature)
+//! - Optional DNS propagation check before validating DNS01 challenges
+//!   (`dns-propagation` feature)
+//! - CNAME delegation of `_acme-challenge` records to a dedicated validation
+//!   zone
+//! - Per-identifier challenge type selection policies
+//! - In-process mock ACME server for running full issuance flows in tests
+//!   (`test-server` feature)
 //! - Fully instrumented with `tracing`
 //!
 //! ## Example
@@ -110,30 +124,90 @@
 //!
 mod account;
 mod authorization;
+mod challenge_policy;
 mod directory;
+mod dns;
+mod dns_delegation;
+#[cfg(feature = "dns-propagation")]
+mod dns_propagation;
+#[cfg(any(feature = "rfc2136", feature = "dns-propagation"))]
+mod dns_wire;
 mod error;
 mod helpers;
+#[cfg(feature = "http01-responder")]
+mod http01;
 mod jws;
 mod order;
+mod renewal;
+mod revocation;
+#[cfg(feature = "rfc2136")]
+mod rfc2136;
+mod self_check;
+mod solver;
+#[cfg(any(test, feature = "test-server"))]
+mod test_server;
+#[cfg(feature = "tls-alpn01-responder")]
+mod tls_alpn01;
+mod webroot;
 
 pub use account::*;
 pub use authorization::*;
+pub use challenge_policy::ChallengePolicy;
 pub use directory::*;
+pub use dns::*;
+pub use dns_delegation::CnameDelegation;
+#[cfg(feature = "dns-propagation")]
+pub use dns_propagation::DnsPropagationCheck;
 pub use error::Error;
 pub use error::ServerError;
 pub use error::TransportError;
 pub use helpers::gen_ec_p256_private_key;
+pub use helpers::gen_ec_p384_private_key;
+pub use helpers::gen_ed25519_private_key;
 pub use helpers::gen_rsa_private_key;
 pub use helpers::Identifier;
+#[cfg(feature = "http01-responder")]
+pub use http01::*;
+pub use jws::AccountSigner;
+pub use jws::HmacAlgorithm;
+pub use jws::Jwk;
+pub use jws::JwsAlgorithm;
 pub use openssl;
 pub use order::*;
+pub use renewal::*;
+pub use revocation::*;
+#[cfg(feature = "rfc2136")]
+pub use rfc2136::DnsUpdateError;
+#[cfg(feature = "rfc2136")]
+pub use rfc2136::Rfc2136Provider;
+#[cfg(feature = "rfc2136")]
+pub use rfc2136::Rfc2136ProviderBuilder;
+#[cfg(feature = "rfc2136")]
+pub use rfc2136::TsigAlgorithm;
+#[cfg(feature = "rfc2136")]
+pub use rfc2136::TsigKey;
+pub use self_check::Http01SelfCheck;
+pub use solver::ChallengeSolver;
+#[cfg(any(test, feature = "test-server"))]
+pub use test_server::ChallengeValidator;
+#[cfg(any(test, feature = "test-server"))]
+pub use test_server::TestServer;
+#[cfg(any(test, feature = "test-server"))]
+pub use test_server::TestServerBuilder;
+#[cfg(any(test, feature = "test-server"))]
+pub use test_server::ValidationRequest;
+#[cfg(feature = "tls-alpn01-responder")]
+pub use tls_alpn01::TlsAlpn01Responder;
+pub use webroot::WebrootSolver;
 
 #[cfg(test)]
 mod tests {
   use crate::*;
+  use async_trait::async_trait;
   use serde_json::json;
   use std::sync::Arc;
   use std::time::Duration;
+  use std::time::SystemTime;
 
   async fn pebble_http_client() -> reqwest::Client {
     let raw = tokio::fs::read("./certs/pebble.minica.pem").await.unwrap();
@@ -154,6 +228,82 @@ mod tests {
       .unwrap()
   }
 
+  /// A software stand-in for a HSM / KMS held account key. It only exposes
+  /// the public JWK and signatures, and counts how often it was used.
+  #[derive(Debug)]
+  struct TestKmsSigner {
+    key: openssl::pkey::PKey<openssl::pkey::Private>,
+    signatures: std::sync::atomic::AtomicUsize,
+  }
+
+  impl TestKmsSigner {
+    fn new() -> Self {
+      TestKmsSigner {
+        key: gen_ec_p256_private_key().unwrap(),
+        signatures: std::sync::atomic::AtomicUsize::new(0),
+      }
+    }
+  }
+
+  #[async_trait]
+  impl AccountSigner for TestKmsSigner {
+    fn algorithm(&self) -> Result<JwsAlgorithm, Error> {
+      Ok(JwsAlgorithm::ES256)
+    }
+
+    fn public_jwk(&self) -> Result<Jwk, Error> {
+      let public = openssl::pkey::PKey::public_key_from_der(
+        &self.key.public_key_to_der().unwrap(),
+      )
+      .unwrap();
+      Jwk::new(&public)
+    }
+
+    async fn sign(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+      self
+        .signatures
+        .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+      self.key.sign(data).await
+    }
+  }
+
+  /// A directory that is not backed by a server, for tests that do not
+  /// need to make any requests.
+  fn offline_directory(meta: serde_json::Value) -> Arc<Directory> {
+    let mut dir: Directory = serde_json::from_value(json!({
+      "newNonce": "https://example.com/acme/new-nonce",
+      "newAccount": "https://example.com/acme/new-account",
+      "newOrder": "https://example.com/acme/new-order",
+      "revokeCert": "https://example.com/acme/revoke-cert",
+      "keyChange": "https://example.com/acme/key-change",
+      "meta": meta
+    }))
+    .unwrap();
+    dir.url = "https://example.com/acme/directory".to_string();
+    Arc::new(dir)
+  }
+
+  /// An account in an [`offline_directory`], for tests that do not need to
+  /// make any requests.
+  fn offline_account(meta: serde_json::Value) -> Arc<Account> {
+    let dir = offline_directory(meta);
+    let stored = StoredAccount {
+      directory_url: dir.url().to_string(),
+      id: "https://example.com/acme/acct/1".to_string(),
+      private_key_pem: None,
+      status: AccountStatus::Valid,
+      contact: None,
+      terms_of_service_agreed: Some(true),
+      orders_url: None,
+    };
+    Account::from_stored_with_signer(
+      dir,
+      stored,
+      Arc::new(TestKmsSigner::new()),
+    )
+    .unwrap()
+  }
+
   async fn pebble_account() -> Arc<Account> {
     let dir = pebble_directory().await;
     let mut builder = AccountBuilder::new(dir);
@@ -171,6 +321,7 @@ mod tests {
   }
 
   #[tokio::test]
+  #[ignore = "requires access to the Let's Encrypt staging environment"]
   async fn test_client_creation_letsencrypt() {
     let dir = DirectoryBuilder::new(
       "https://acme-staging-v02.api.letsencrypt.org/directory".to_string(),
@@ -192,6 +343,7 @@ mod tests {
   }
 
   #[tokio::test]
+  #[ignore = "requires Pebble, see docker-compose.yml"]
   async fn test_client_creation_pebble() {
     let dir = pebble_directory().await;
 
@@ -202,6 +354,7 @@ mod tests {
   }
 
   #[tokio::test]
+  #[ignore = "requires Pebble, see docker-compose.yml"]
   async fn test_account_creation_pebble() {
     let dir = pebble_directory().await;
 
@@ -229,6 +382,2415 @@ mod tests {
   }
 
   #[tokio::test]
+  async fn test_account_creation_ec() {
+    let server = TestServer::start().await.unwrap();
+    let dir = test_server_directory(&server).await;
+
+    for pkey in [
+      gen_ec_p256_private_key().unwrap(),
+      gen_ec_p384_private_key().unwrap(),
+    ] {
+      let mut builder = AccountBuilder::new(dir.clone());
+      let account = builder
+        .private_key(pkey)
+        .contact(vec!["mailto:hello@lcas.dev".to_string()])
+        .terms_of_service_agreed(true)
+        .build()
+        .await
+        .unwrap();
+
+      assert!(!account.id.is_empty());
+      assert_eq!(account.status, AccountStatus::Valid);
+    }
+  }
+
+  #[tokio::test]
+  async fn test_jws_ec_signature() {
+    use openssl::bn::BigNum;
+    use openssl::ecdsa::EcdsaSig;
+    use openssl::hash::hash;
+    use openssl::hash::MessageDigest;
+
+    for (pkey, alg, crv, size, digest) in [
+      (
+        gen_ec_p256_private_key().unwrap(),
+        "ES256",
+        "P-256",
+        32,
+        MessageDigest::sha256(),
+      ),
+      (
+        gen_ec_p384_private_key().unwrap(),
+        "ES384",
+        "P-384",
+        48,
+        MessageDigest::sha384(),
+      ),
+    ] {
+      let body = crate::jws::jws(
+        "https://example.com/acme/new-account",
+        "nonce".to_string(),
+        "{}",
+        &pkey,
+        None,
+      )
+      .await
+      .unwrap();
+      let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+
+      let decode = |field: &str| {
+        base64::decode_config(
+          body[field].as_str().unwrap(),
+          base64::URL_SAFE_NO_PAD,
+        )
+        .unwrap()
+      };
+
+      let header: serde_json::Value =
+        serde_json::from_slice(&decode("protected")).unwrap();
+      assert_eq!(header["alg"], alg);
+      assert_eq!(header["jwk"]["kty"], "EC");
+      assert_eq!(header["jwk"]["crv"], crv);
+      assert!(header["jwk"]["x"].is_string());
+      assert!(header["jwk"]["y"].is_string());
+
+      let signature = decode("signature");
+      assert_eq!(signature.len(), size * 2);
+      let sig = EcdsaSig::from_private_components(
+        BigNum::from_slice(&signature[..size]).unwrap(),
+        BigNum::from_slice(&signature[size..]).unwrap(),
+      )
+      .unwrap();
+      let input = format!(
+        "{}.{}",
+        body["protected"].as_str().unwrap(),
+        body["payload"].as_str().unwrap()
+      );
+      let digest = hash(digest, input.as_bytes()).unwrap();
+      assert!(sig.verify(&digest, &pkey.ec_key().unwrap()).unwrap());
+    }
+  }
+
+  #[tokio::test]
+  async fn test_jws_ed25519_signature() {
+    use openssl::sign::Verifier;
+
+    let pkey = gen_ed25519_private_key().unwrap();
+    let body = crate::jws::jws(
+      "https://example.com/acme/new-account",
+      "nonce".to_string(),
+      "{}",
+      &pkey,
+      None,
+    )
+    .await
+    .unwrap();
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+
+    let decode = |field: &str| {
+      base64::decode_config(
+        body[field].as_str().unwrap(),
+        base64::URL_SAFE_NO_PAD,
+      )
+      .unwrap()
+    };
+
+    let header: serde_json::Value =
+      serde_json::from_slice(&decode("protected")).unwrap();
+    assert_eq!(header["alg"], "EdDSA");
+    assert_eq!(header["jwk"]["kty"], "OKP");
+    assert_eq!(header["jwk"]["crv"], "Ed25519");
+
+    let input = format!(
+      "{}.{}",
+      body["protected"].as_str().unwrap(),
+      body["payload"].as_str().unwrap()
+    );
+    let mut verifier = Verifier::new_without_digest(&pkey).unwrap();
+    assert!(verifier
+      .verify_oneshot(&decode("signature"), input.as_bytes())
+      .unwrap());
+  }
+
+  #[tokio::test]
+  async fn test_jws_custom_signer() {
+    let signer = TestKmsSigner::new();
+    let body = crate::jws::jws(
+      "https://example.com/acme/new-order",
+      "nonce".to_string(),
+      "{}",
+      &signer,
+      Some("https://example.com/acme/acct/1".to_string()),
+    )
+    .await
+    .unwrap();
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let protected = base64::decode_config(
+      body["protected"].as_str().unwrap(),
+      base64::URL_SAFE_NO_PAD,
+    )
+    .unwrap();
+    let header: serde_json::Value = serde_json::from_slice(&protected).unwrap();
+
+    assert_eq!(header["alg"], "ES256");
+    assert_eq!(header["kid"], "https://example.com/acme/acct/1");
+    assert!(header.get("jwk").is_none());
+    assert_eq!(
+      signer.signatures.load(std::sync::atomic::Ordering::SeqCst),
+      1
+    );
+    assert_eq!(signer.public_jwk().unwrap(), Jwk::new(&signer.key).unwrap());
+    assert!(signer.private_key().is_none());
+  }
+
+  #[tokio::test]
+  async fn test_account_creation_custom_signer() {
+    let server = TestServer::start().await.unwrap();
+    let dir = test_server_directory(&server).await;
+    let signer = Arc::new(TestKmsSigner::new());
+
+    let mut builder = AccountBuilder::new(dir);
+    let account = builder
+      .signer(signer.clone())
+      .contact(vec!["mailto:hello@lcas.dev".to_string()])
+      .terms_of_service_agreed(true)
+      .build()
+      .await
+      .unwrap();
+
+    assert!(!account.id.is_empty());
+    assert_eq!(account.status, AccountStatus::Valid);
+    assert!(account.private_key().is_none());
+    assert!(signer.signatures.load(std::sync::atomic::Ordering::SeqCst) > 0);
+  }
+
+  #[test]
+  fn test_eab_jws() {
+    use openssl::pkey::PKey;
+    use openssl::sign::Signer;
+
+    let pkey = gen_ec_p256_private_key().unwrap();
+    let jwk = Jwk::new(&pkey).unwrap();
+    let hmac_key = b"super-secret-hmac-key".to_vec();
+
+    let eab = crate::jws::eab_jws(
+      "https://example.com/acme/new-account",
+      "kid-1",
+      &hmac_key,
+      HmacAlgorithm::HS384,
+      &jwk,
+    )
+    .unwrap();
+
+    let decode = |field: &str| {
+      base64::decode_config(
+        eab[field].as_str().unwrap(),
+        base64::URL_SAFE_NO_PAD,
+      )
+      .unwrap()
+    };
+
+    let header: serde_json::Value =
+      serde_json::from_slice(&decode("protected")).unwrap();
+    assert_eq!(
+      header,
+      json!({
+        "alg": "HS384",
+        "kid": "kid-1",
+        "url": "https://example.com/acme/new-account"
+      })
+    );
+    let payload: Jwk = serde_json::from_slice(&decode("payload")).unwrap();
+    assert_eq!(payload, jwk);
+
+    let key = PKey::hmac(&hmac_key).unwrap();
+    let mut signer =
+      Signer::new(openssl::hash::MessageDigest::sha384(), &key).unwrap();
+    signer
+      .update(
+        format!(
+          "{}.{}",
+          eab["protected"].as_str().unwrap(),
+          eab["payload"].as_str().unwrap()
+        )
+        .as_bytes(),
+      )
+      .unwrap();
+    assert_eq!(decode("signature"), signer.sign_to_vec().unwrap());
+  }
+
+  #[tokio::test]
+  async fn test_account_creation_eab_required() {
+    let dir = offline_directory(json!({ "externalAccountRequired": true }));
+
+    let mut builder = AccountBuilder::new(dir);
+    let err = builder
+      .private_key(gen_ec_p256_private_key().unwrap())
+      .terms_of_service_agreed(true)
+      .build()
+      .await
+      .unwrap_err();
+
+    assert!(matches!(err, Error::Validation(_)));
+  }
+
+  #[tokio::test]
+  async fn test_account_update_and_deactivate() {
+    let server = TestServer::start().await.unwrap();
+    let account =
+      test_server_account(&server, gen_ec_p256_private_key().unwrap()).await;
+
+    let account = account
+      .update_contact(vec!["mailto:acme@lcas.dev".to_string()])
+      .await
+      .unwrap();
+    assert_eq!(
+      account.contact,
+      Some(vec!["mailto:acme@lcas.dev".to_string()])
+    );
+    assert_eq!(account.status, AccountStatus::Valid);
+
+    let account = account.agree_to_terms().await.unwrap();
+    assert_eq!(account.status, AccountStatus::Valid);
+
+    let account = account.deactivate().await.unwrap();
+    assert_eq!(account.status, AccountStatus::Deactivated);
+  }
+
+  #[tokio::test]
+  async fn test_account_orders() {
+    let server = TestServer::start().await.unwrap();
+    let account =
+      test_server_account(&server, gen_ec_p256_private_key().unwrap()).await;
+
+    let mut order_urls = vec![];
+    for _ in 0..2 {
+      let mut builder = OrderBuilder::new(account.clone());
+      let order = builder
+        .add_dns_identifier("orders.example.com".to_string())
+        .build()
+        .await
+        .unwrap();
+      order_urls.push(order.url.clone());
+    }
+
+    let mut orders = account.orders().unwrap();
+    let mut listed = vec![];
+    while let Some(url) = orders.next().await.unwrap() {
+      listed.push(url);
+    }
+    for url in &order_urls {
+      assert!(listed.contains(url));
+    }
+
+    let order = account.order(order_urls[0].clone()).await.unwrap();
+    assert_eq!(order.status, OrderStatus::Pending);
+    assert_eq!(order.url, order_urls[0]);
+  }
+
+  #[test]
+  fn test_extract_links() {
+    use reqwest::header::HeaderMap;
+    use reqwest::header::HeaderValue;
+    use reqwest::header::LINK;
+
+    let mut headers = HeaderMap::new();
+    headers.append(
+      LINK,
+      HeaderValue::from_static(
+        r#"<https://example.com/acme/directory>;rel="index", </acme/orders/1?cursor=2>; rel="next""#,
+      ),
+    );
+    headers.append(
+      LINK,
+      HeaderValue::from_static(
+        "<https://example.com/acme/cert/1/1>;rel=alternate",
+      ),
+    );
+
+    let base = "https://example.com/acme/orders/1";
+    assert_eq!(
+      crate::helpers::extract_links(&headers, base, "next"),
+      vec!["https://example.com/acme/orders/1?cursor=2".to_string()]
+    );
+    assert_eq!(
+      crate::helpers::extract_links(&headers, base, "alternate"),
+      vec!["https://example.com/acme/cert/1/1".to_string()]
+    );
+    assert!(crate::helpers::extract_links(&headers, base, "up").is_empty());
+  }
+
+  #[test]
+  fn test_account_stored_roundtrip() {
+    let dir = offline_directory(json!({}));
+    let pkey = gen_ed25519_private_key().unwrap();
+
+    let stored = StoredAccount {
+      directory_url: dir.url().to_string(),
+      id: "https://example.com/acme/acct/1".to_string(),
+      private_key_pem: Some(
+        String::from_utf8(pkey.private_key_to_pem_pkcs8().unwrap()).unwrap(),
+      ),
+      status: AccountStatus::Valid,
+      contact: Some(vec!["mailto:hello@lcas.dev".to_string()]),
+      terms_of_service_agreed: Some(true),
+      orders_url: None,
+    };
+    let json = serde_json::to_string(&stored).unwrap();
+    let stored: StoredAccount = serde_json::from_str(&json).unwrap();
+
+    let account = Account::from_stored(dir.clone(), stored).unwrap();
+    assert_eq!(account.id, "https://example.com/acme/acct/1");
+    assert_eq!(account.status, AccountStatus::Valid);
+    assert!(account.private_key().unwrap().public_eq(&pkey));
+
+    let restored = account.to_stored().unwrap();
+    assert_eq!(restored.directory_url, dir.url());
+    assert_eq!(restored.id, account.id);
+    assert_eq!(restored.contact, account.contact);
+
+    // A custom signer without an exportable key can still be stored, but
+    // must be restored with the signer.
+    let signer = Arc::new(TestKmsSigner::new());
+    let account =
+      Account::from_stored_with_signer(dir.clone(), restored, signer.clone())
+        .unwrap();
+    let stored = account.to_stored().unwrap();
+    assert!(stored.private_key_pem.is_none());
+    assert!(matches!(
+      Account::from_stored(dir.clone(), stored.clone()),
+      Err(Error::Validation(_))
+    ));
+
+    let other_dir = offline_directory(json!({}));
+    let mut stored = stored;
+    stored.directory_url = "https://example.org/directory".to_string();
+    assert!(matches!(
+      Account::from_stored_with_signer(other_dir, stored, signer),
+      Err(Error::Validation(_))
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_revoke_certificate() {
+    let server = TestServer::start().await.unwrap();
+    let account =
+      test_server_account(&server, gen_ec_p256_private_key().unwrap()).await;
+    let (_, certs) =
+      test_server_certificate(account.clone(), "revoke.example.com").await;
+
+    account
+      .revoke_certificate(&certs[0], Some(RevocationReason::Superseded))
+      .await
+      .unwrap();
+
+    // Revoking an already revoked certificate is an error.
+    let err = account
+      .revoke_certificate(&certs[0], None)
+      .await
+      .unwrap_err();
+    assert!(matches!(err, Error::Server(_)));
+  }
+
+  #[tokio::test]
+  async fn test_revoke_certificate_with_key() {
+    let server = TestServer::start().await.unwrap();
+    let account =
+      test_server_account(&server, gen_ec_p256_private_key().unwrap()).await;
+    let (pkey, certs) =
+      test_server_certificate(account.clone(), "revoke-with-key.example.com")
+        .await;
+
+    let dir = account.directory.clone().unwrap();
+    dir
+      .revoke_certificate_with_key(
+        &certs[0],
+        &pkey,
+        Some(RevocationReason::KeyCompromise),
+      )
+      .await
+      .unwrap();
+  }
+
+  #[test]
+  fn test_certificate_identifier() {
+    use openssl::asn1::Asn1Object;
+    use openssl::asn1::Asn1OctetString;
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::x509::X509Extension;
+    use openssl::x509::X509;
+
+    // Example from RFC 9773, section 4.1.
+    let key_id = [
+      0x69, 0x88, 0x5B, 0x6B, 0x87, 0x46, 0x40, 0x41, 0xE1, 0xB3, 0x7B, 0x84,
+      0x7B, 0xA0, 0xAE, 0x2C, 0xDE, 0x01, 0xC8, 0xD4,
+    ];
+    let mut aki = vec![0x30, 0x16, 0x80, 0x14];
+    aki.extend_from_slice(&key_id);
+
+    let pkey = gen_ec_p256_private_key().unwrap();
+    let mut builder = X509::builder().unwrap();
+    builder
+      .set_serial_number(
+        &BigNum::from_hex_str("0087654321")
+          .unwrap()
+          .to_asn1_integer()
+          .unwrap(),
+      )
+      .unwrap();
+    builder
+      .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+      .unwrap();
+    builder
+      .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+      .unwrap();
+    builder.set_pubkey(&pkey).unwrap();
+    builder
+      .append_extension(
+        X509Extension::new_from_der(
+          &Asn1Object::from_str("2.5.29.35").unwrap(),
+          false,
+          &Asn1OctetString::new_from_bytes(&aki).unwrap(),
+        )
+        .unwrap(),
+      )
+      .unwrap();
+    builder
+      .sign(&pkey, openssl::hash::MessageDigest::sha256())
+      .unwrap();
+    let cert = builder.build();
+
+    assert_eq!(
+      certificate_identifier(&cert).unwrap(),
+      "aYhba4dGQEHhs3uEe6CuLN4ByNQ.AIdlQyE"
+    );
+  }
+
+  #[test]
+  fn test_extract_retry_after() {
+    use reqwest::header::HeaderMap;
+    use reqwest::header::HeaderValue;
+    use reqwest::header::RETRY_AFTER;
+
+    let mut headers = HeaderMap::new();
+    assert_eq!(crate::helpers::extract_retry_after(&headers), None);
+
+    headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+    assert_eq!(
+      crate::helpers::extract_retry_after(&headers),
+      Some(Duration::from_secs(120))
+    );
+
+    headers.insert(
+      RETRY_AFTER,
+      HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+    );
+    assert_eq!(
+      crate::helpers::extract_retry_after(&headers),
+      Some(Duration::from_secs(0))
+    );
+  }
+
+  #[test]
+  fn test_suggested_window_random_time() {
+    let info: RenewalInfo = serde_json::from_value(json!({
+      "suggestedWindow": {
+        "start": "2025-01-02T04:00:00Z",
+        "end": "2025-01-03T04:00:00Z"
+      },
+      "explanationURL": "https://example.com/docs/ari"
+    }))
+    .unwrap();
+
+    let window = &info.suggested_window;
+    assert_eq!(
+      window.start,
+      SystemTime::UNIX_EPOCH + Duration::from_secs(1735790400)
+    );
+    for _ in 0..100 {
+      let time = window.random_time().unwrap();
+      assert!(time >= window.start && time <= window.end);
+    }
+    assert_eq!(
+      info.explanation_url,
+      Some("https://example.com/docs/ari".to_string())
+    );
+  }
+
+  #[tokio::test]
+  async fn test_renewal_info() {
+    let server = TestServer::start().await.unwrap();
+    let account =
+      test_server_account(&server, gen_ec_p256_private_key().unwrap()).await;
+    let (_, certs) =
+      test_server_certificate(account.clone(), "renewal-info.example.com")
+        .await;
+
+    let dir = account.directory.clone().unwrap();
+    let info = dir.renewal_info(&certs[0]).await.unwrap();
+    assert!(info.suggested_window.start < info.suggested_window.end);
+
+    let mut builder = OrderBuilder::new(account);
+    let order = builder
+      .add_dns_identifier("renewal-info.example.com".to_string())
+      .replaces(certificate_identifier(&certs[0]).unwrap())
+      .build()
+      .await
+      .unwrap();
+    assert_eq!(
+      order.replaces,
+      Some(certificate_identifier(&certs[0]).unwrap())
+    );
+  }
+
+  #[tokio::test]
+  async fn test_order_profile_validation() {
+    let account = offline_account(json!({}));
+    let mut builder = OrderBuilder::new(account);
+    let err = builder
+      .add_dns_identifier("example.com".to_string())
+      .profile("shortlived".to_string())
+      .build()
+      .await
+      .unwrap_err();
+    assert!(matches!(err, Error::Validation(_)));
+
+    let account = offline_account(json!({
+      "profiles": {
+        "classic": "The same profile you're accustomed to",
+        "tlsserver": "https://letsencrypt.org/docs/profiles#tlsserver"
+      }
+    }));
+    let meta = account.directory.clone().unwrap().meta.clone().unwrap();
+    assert_eq!(meta.profiles.unwrap().len(), 2);
+
+    let mut builder = OrderBuilder::new(account);
+    let err = builder
+      .add_dns_identifier("example.com".to_string())
+      .profile("shortlived".to_string())
+      .build()
+      .await
+      .unwrap_err();
+    assert!(matches!(err, Error::Validation(_)));
+  }
+
+  #[tokio::test]
+  async fn test_order_profile() {
+    let server = TestServerBuilder::new()
+      .profile(
+        "shortlived".to_string(),
+        "Certificates that are valid for a few days".to_string(),
+      )
+      .build()
+      .await
+      .unwrap();
+    let account =
+      test_server_account(&server, gen_ec_p256_private_key().unwrap()).await;
+    let meta = account.directory.clone().unwrap().meta.clone().unwrap();
+    assert!(meta.profiles.unwrap().contains_key("shortlived"));
+
+    let mut builder = OrderBuilder::new(account);
+    let order = builder
+      .add_dns_identifier("profile.example.com".to_string())
+      .profile("shortlived".to_string())
+      .build()
+      .await
+      .unwrap();
+    assert_eq!(order.profile, Some("shortlived".to_string()));
+  }
+
+  #[tokio::test]
+  async fn test_order_validity_validation() {
+    let now = SystemTime::now();
+    let account = offline_account(json!({}));
+    let mut builder = OrderBuilder::new(account);
+    let err = builder
+      .add_dns_identifier("example.com".to_string())
+      .not_before(now)
+      .not_after(now - Duration::from_secs(60 * 60))
+      .build()
+      .await
+      .unwrap_err();
+    assert!(matches!(err, Error::Validation(_)));
+  }
+
+  #[tokio::test]
+  async fn test_order_validity() {
+    let server = TestServer::start().await.unwrap();
+    let account =
+      test_server_account(&server, gen_ec_p256_private_key().unwrap()).await;
+    // 2030-01-01T00:00:00Z
+    let not_before = SystemTime::UNIX_EPOCH + Duration::from_secs(1893456000);
+    let not_after = not_before + Duration::from_secs(7 * 24 * 60 * 60);
+
+    let mut builder = OrderBuilder::new(account);
+    let order = builder
+      .add_dns_identifier("validity.example.com".to_string())
+      .not_before(not_before)
+      .not_after(not_after)
+      .build()
+      .await
+      .unwrap();
+
+    assert_eq!(order.not_before.as_deref(), Some("2030-01-01T00:00:00Z"));
+    assert_eq!(order.not_after.as_deref(), Some("2030-01-08T00:00:00Z"));
+  }
+
+  #[test]
+  fn test_identifier_helpers() {
+    use std::net::IpAddr;
+
+    let dns = Identifier::dns("example.com".to_string());
+    assert!(dns.is_dns());
+    assert!(!dns.is_ip());
+    assert_eq!(dns.ip_addr(), None);
+    assert_eq!(dns.http_host(), "example.com");
+    assert_eq!(dns.tls_server_name(), "example.com");
+    assert_eq!(
+      dns.dns01_record_name().unwrap(),
+      "_acme-challenge.example.com"
+    );
+    let wildcard = Identifier::dns("*.example.com.".to_string());
+    assert_eq!(
+      wildcard.dns01_record_name().unwrap(),
+      "_acme-challenge.example.com"
+    );
+
+    let v4: IpAddr = "192.0.2.10".parse().unwrap();
+    let ip = Identifier::ip(v4);
+    assert!(ip.is_ip());
+    assert_eq!(ip.value, "192.0.2.10");
+    assert_eq!(ip.ip_addr(), Some(v4));
+    assert_eq!(ip.http_host(), "192.0.2.10");
+    assert_eq!(ip.tls_server_name(), "10.2.0.192.in-addr.arpa");
+    assert_eq!(ip.dns01_record_name(), None);
+
+    let v6: IpAddr = "2001:db8::1".parse().unwrap();
+    let ip = Identifier::ip(v6);
+    assert_eq!(ip.value, "2001:db8::1");
+    assert_eq!(ip.http_host(), "[2001:db8::1]");
+    assert_eq!(
+      ip.tls_server_name(),
+      "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+    );
+  }
+
+  #[test]
+  fn test_gen_csr_ip_identifiers() {
+    let pkey = gen_ec_p256_private_key().unwrap();
+
+    let csr = crate::order::gen_csr(
+      &pkey,
+      &[
+        Identifier::ip("192.0.2.10".parse().unwrap()),
+        Identifier::dns("example.com".to_string()),
+      ],
+    )
+    .unwrap();
+    let text = String::from_utf8(csr.to_text().unwrap()).unwrap();
+    assert!(
+      text.contains("CN=example.com") || text.contains("CN = example.com")
+    );
+    assert!(text.contains("IP Address:192.0.2.10"));
+    assert!(text.contains("DNS:example.com"));
+
+    let csr = crate::order::gen_csr(
+      &pkey,
+      &[Identifier::ip("2001:db8::1".parse().unwrap())],
+    )
+    .unwrap();
+    assert_eq!(csr.subject_name().entries().count(), 0);
+    let text = String::from_utf8(csr.to_text().unwrap()).unwrap();
+    assert!(text.contains("IP Address:2001:DB8:0:0:0:0:0:1"));
+  }
+
+  #[tokio::test]
+  async fn test_order_ip_identifier() {
+    let server = TestServer::start().await.unwrap();
+    let account =
+      test_server_account(&server, gen_ec_p256_private_key().unwrap()).await;
+
+    let mut builder = OrderBuilder::new(account);
+    let order = builder
+      .add_ip_identifier("192.0.2.3".parse().unwrap())
+      .build()
+      .await
+      .unwrap();
+    assert!(order.identifiers[0].is_ip());
+
+    for auth in order.authorizations().await.unwrap() {
+      assert!(auth.identifier.is_ip());
+      assert!(auth.get_challenge("dns-01").is_none());
+      let challenge = auth.get_challenge("http-01").unwrap();
+      let challenge = challenge.validate().await.unwrap();
+      let challenge = challenge
+        .wait_done(Duration::from_millis(10), 3)
+        .await
+        .unwrap();
+      assert_eq!(challenge.status, ChallengeStatus::Valid);
+    }
+
+    let order = order
+      .wait_ready(Duration::from_millis(10), 3)
+      .await
+      .unwrap();
+    let pkey = gen_ec_p256_private_key().unwrap();
+    let order = order.finalize(Csr::Automatic(pkey)).await.unwrap();
+    let order = order.wait_done(Duration::from_millis(10), 3).await.unwrap();
+    assert_eq!(order.status, OrderStatus::Valid);
+
+    let certs = order.certificate().await.unwrap().unwrap();
+    let ips: Vec<_> = certs[0]
+      .subject_alt_names()
+      .unwrap()
+      .iter()
+      .filter_map(|name| name.ipaddress().map(|ip| ip.to_vec()))
+      .collect();
+    assert_eq!(ips, vec![vec![192, 0, 2, 3]]);
+  }
+
+  #[test]
+  fn test_select_chain() {
+    use crate::order::select_chain;
+    use openssl::asn1::Asn1Time;
+    use openssl::x509::X509Name;
+    use openssl::x509::X509;
+
+    fn cert(subject: &str, issuer: &str) -> X509 {
+      let pkey = gen_ec_p256_private_key().unwrap();
+      let name = |cn: &str| {
+        let mut name = X509Name::builder().unwrap();
+        name.append_entry_by_text("CN", cn).unwrap();
+        name.build()
+      };
+      let mut builder = X509::builder().unwrap();
+      builder.set_subject_name(&name(subject)).unwrap();
+      builder.set_issuer_name(&name(issuer)).unwrap();
+      builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+      builder
+        .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+      builder.set_pubkey(&pkey).unwrap();
+      builder
+        .sign(&pkey, openssl::hash::MessageDigest::sha256())
+        .unwrap();
+      builder.build()
+    }
+
+    let chains = vec![
+      vec![
+        cert("example.com", "Intermediate A"),
+        cert("Intermediate A", "Cross Root"),
+      ],
+      vec![
+        cert("example.com", "Intermediate B"),
+        cert("Intermediate B", "Root B"),
+        cert("Root B", "Legacy Root"),
+      ],
+      vec![cert("example.com", "Intermediate C")],
+    ];
+
+    let pref = ChainPreference::RootCommonName("Legacy Root".to_string());
+    assert_eq!(select_chain(&chains, &pref), Some(1));
+    let pref = ChainPreference::RootCommonName("Intermediate B".to_string());
+    assert_eq!(select_chain(&chains, &pref), None);
+    let pref = ChainPreference::IssuerCommonName("Intermediate B".to_string());
+    assert_eq!(select_chain(&chains, &pref), Some(1));
+    let pref = ChainPreference::IssuerCommonName("Unknown".to_string());
+    assert_eq!(select_chain(&chains, &pref), None);
+    assert_eq!(select_chain(&chains, &ChainPreference::Shortest), Some(2));
+  }
+
+  #[tokio::test]
+  async fn test_certificate_chains() {
+    let server = TestServer::start().await.unwrap();
+    let account =
+      test_server_account(&server, gen_ec_p256_private_key().unwrap()).await;
+    let (_, order) =
+      test_server_valid_order(account, "chains.example.com").await;
+
+    let chains = order.certificate_chains().await.unwrap().unwrap();
+    assert_eq!(chains.len(), 2);
+
+    let common_name = |name: &openssl::x509::X509NameRef| {
+      name
+        .entries_by_nid(openssl::nid::Nid::COMMONNAME)
+        .next()
+        .unwrap()
+        .data()
+        .to_string()
+        .unwrap()
+    };
+    let root = common_name(server.alternate_root_certificate().subject_name());
+    assert_eq!(common_name(chains[1].last().unwrap().issuer_name()), root);
+    assert_ne!(common_name(chains[0].last().unwrap().issuer_name()), root);
+
+    let cert = order
+      .certificate_with_preference(ChainPreference::RootCommonName(root))
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(cert.len(), chains[1].len());
+  }
+
+  #[test]
+  fn test_tls_alpn01_certificate() {
+    use openssl::hash::hash;
+    use openssl::hash::MessageDigest;
+
+    let mut challenge: Challenge = serde_json::from_value(json!({
+      "type": "tls-alpn-01",
+      "url": "https://example.com/acme/chall/1",
+      "status": "pending",
+      "token": "evaGxfADs6pSRb2LAv9IZf17Dt3juxGJ-PCt92wr-oA"
+    }))
+    .unwrap();
+    challenge.account = Some(offline_account(json!({})));
+    challenge.identifier = Some(Identifier::dns("example.com".to_string()));
+
+    let (pkey, cert) = challenge.tls_alpn01_certificate().unwrap().unwrap();
+    assert!(cert.public_key().unwrap().public_eq(&pkey));
+    assert_eq!(
+      cert.subject_alt_names().unwrap()[0].dnsname(),
+      Some("example.com")
+    );
+
+    // Extension ::= SEQUENCE { id-pe-acmeIdentifier, critical TRUE,
+    // OCTET STRING { OCTET STRING (SHA-256 of the key authorization) } }
+    let key_authorization = challenge.key_authorization().unwrap().unwrap();
+    let digest =
+      hash(MessageDigest::sha256(), key_authorization.as_bytes()).unwrap();
+    let mut extension = vec![
+      0x30, 0x31, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x1f,
+      0x01, 0x01, 0xff, 0x04, 0x22, 0x04, 0x20,
+    ];
+    extension.extend_from_slice(&digest);
+    let der = cert.to_der().unwrap();
+    assert!(der.windows(extension.len()).any(|w| w == &extension[..]));
+
+    challenge.identifier = Some(Identifier::ip("10.30.50.3".parse().unwrap()));
+    let (_, cert) = challenge.tls_alpn01_certificate().unwrap().unwrap();
+    assert_eq!(
+      cert.subject_alt_names().unwrap()[0].ipaddress(),
+      Some(&[10, 30, 50, 3][..])
+    );
+
+    challenge.token = None;
+    assert!(challenge.tls_alpn01_certificate().unwrap().is_none());
+  }
+
+  #[cfg(feature = "http01-responder")]
+  #[tokio::test]
+  async fn test_http01_responder() {
+    use hyper::service::service_fn;
+    use hyper::service::Service;
+    use hyper::Body;
+    use hyper::Request;
+    use hyper::Response;
+    use hyper::StatusCode;
+
+    let mut challenge: Challenge = serde_json::from_value(json!({
+      "type": "http-01",
+      "url": "https://example.com/acme/chall/1",
+      "status": "pending",
+      "token": "LoqXcYV8q5ONbJQxbmR7SCTNo3tiAXDfowyjxAjEuX0"
+    }))
+    .unwrap();
+    challenge.account = Some(offline_account(json!({})));
+    let key_authorization = challenge.key_authorization().unwrap().unwrap();
+
+    let responder = Http01Responder::new();
+    responder.register(&challenge).unwrap();
+    assert_eq!(
+      responder.key_authorization(challenge.token.as_deref().unwrap()),
+      Some(key_authorization.clone())
+    );
+
+    let path = format!(
+      "{}{}",
+      ACME_CHALLENGE_PATH_PREFIX,
+      challenge.token.as_deref().unwrap()
+    );
+    let request = |path: &str| Request::get(path).body(Body::empty()).unwrap();
+
+    let mut service = responder.service();
+    let res = service.call(request(&path)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    assert_eq!(body, key_authorization.as_bytes());
+    let res = service.call(request("/")).await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let mut service = responder.wrap(service_fn(|_| async {
+      Ok::<_, std::convert::Infallible>(Response::new(Body::from("inner")))
+    }));
+    let res = service.call(request("/index.html")).await.unwrap();
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    assert_eq!(body, "inner");
+    let res = service.call(request(&path)).await.unwrap();
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    assert_eq!(body, key_authorization.as_bytes());
+
+    responder.remove(&challenge);
+    let res = service.call(request(&path)).await.unwrap();
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    assert_eq!(body, "inner");
+
+    challenge.r#type = "dns-01".to_string();
+    assert!(responder.register(&challenge).is_err());
+  }
+
+  #[cfg(feature = "tls-alpn01-responder")]
+  #[test]
+  fn test_tls_alpn01_responder() {
+    use openssl::asn1::Asn1Time;
+    use openssl::ssl::SslAcceptor;
+    use openssl::ssl::SslConnector;
+    use openssl::ssl::SslMethod;
+    use openssl::ssl::SslVerifyMode;
+    use openssl::x509::X509;
+    use std::net::TcpListener;
+    use std::net::TcpStream;
+
+    let mut challenge: Challenge = serde_json::from_value(json!({
+      "type": "tls-alpn-01",
+      "url": "https://example.com/acme/chall/1",
+      "status": "pending",
+      "token": "evaGxfADs6pSRb2LAv9IZf17Dt3juxGJ-PCt92wr-oA"
+    }))
+    .unwrap();
+    challenge.account = Some(offline_account(json!({})));
+    challenge.identifier = Some(Identifier::dns("example.com".to_string()));
+
+    let responder = TlsAlpn01Responder::new();
+    responder.register(&challenge).unwrap();
+
+    let app_key = gen_ec_p256_private_key().unwrap();
+    let mut app_cert = X509::builder().unwrap();
+    app_cert
+      .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+      .unwrap();
+    app_cert
+      .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+      .unwrap();
+    app_cert.set_pubkey(&app_key).unwrap();
+    app_cert
+      .sign(&app_key, openssl::hash::MessageDigest::sha256())
+      .unwrap();
+    let app_cert = app_cert.build();
+
+    let mut acceptor =
+      SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+    acceptor.set_private_key(&app_key).unwrap();
+    acceptor.set_certificate(&app_cert).unwrap();
+    responder.configure(&mut acceptor, b"\x02h2\x08http/1.1");
+    let acceptor = acceptor.build();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+      for stream in listener.incoming() {
+        let _ = acceptor.accept(stream.unwrap());
+      }
+    });
+
+    let connect = |server_name: &str, protocols: &[u8]| {
+      let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+      connector.set_verify(SslVerifyMode::NONE);
+      connector.set_alpn_protos(protocols).unwrap();
+      let connector = connector.build();
+      let stream = TcpStream::connect(addr).unwrap();
+      connector.connect(server_name, stream)
+    };
+
+    let stream = connect("example.com", b"\x0aacme-tls/1").unwrap();
+    let ssl = stream.ssl();
+    assert_eq!(ssl.selected_alpn_protocol(), Some(&b"acme-tls/1"[..]));
+    let peer = ssl.peer_certificate().unwrap();
+    assert_eq!(
+      peer.subject_alt_names().unwrap()[0].dnsname(),
+      Some("example.com")
+    );
+
+    let stream = connect("example.com", b"\x08http/1.1").unwrap();
+    let ssl = stream.ssl();
+    assert_eq!(ssl.selected_alpn_protocol(), Some(&b"http/1.1"[..]));
+    let peer = ssl.peer_certificate().unwrap();
+    assert_eq!(peer.to_der().unwrap(), app_cert.to_der().unwrap());
+
+    assert!(connect("other.example.com", b"\x0aacme-tls/1").is_err());
+
+    responder.remove(&challenge);
+    assert!(connect("example.com", b"\x0aacme-tls/1").is_err());
+  }
+
+  #[tokio::test]
+  async fn test_challenge_solver_cleanup() {
+    use crate::solver::select_challenge;
+    use crate::solver::solve_challenge;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    #[derive(Debug, Default)]
+    struct FailingSolver {
+      cleanups: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ChallengeSolver for FailingSolver {
+      fn supported_types(&self) -> &[&str] {
+        &["tls-alpn-01", "dns-01"]
+      }
+
+      async fn present(
+        &self,
+        _authorization: &Authorization,
+        _challenge: &Challenge,
+      ) -> Result<(), Error> {
+        Err(Error::Validation("present failed"))
+      }
+
+      async fn cleanup(
+        &self,
+        _authorization: &Authorization,
+        _challenge: &Challenge,
+      ) -> Result<(), Error> {
+        self.cleanups.fetch_add(1, Ordering::SeqCst);
+        Err(Error::Validation("cleanup failed"))
+      }
+    }
+
+    let authorization: Authorization = serde_json::from_value(json!({
+      "identifier": { "type": "dns", "value": "example.com" },
+      "status": "pending",
+      "challenges": [
+        {
+          "type": "http-01",
+          "url": "https://example.com/acme/chall/1",
+          "status": "pending",
+          "token": "LoqXcYV8q5ONbJQxbmR7SCTNo3tiAXDfowyjxAjEuX0"
+        },
+        {
+          "type": "dns-01",
+          "url": "https://example.com/acme/chall/2",
+          "status": "pending",
+          "token": "evaGxfADs6pSRb2LAv9IZf17Dt3juxGJ-PCt92wr-oA"
+        }
+      ]
+    }))
+    .unwrap();
+
+    let solver = FailingSolver::default();
+    let challenge = select_challenge(&authorization, &solver).unwrap();
+    assert_eq!(challenge.r#type, "dns-01");
+    assert!(select_challenge(&authorization, &NoopSolver).is_some());
+
+    let err = solve_challenge(
+      &authorization,
+      challenge,
+      &solver,
+      Duration::from_secs(1),
+      1,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.to_string(), "validation error: present failed");
+    assert_eq!(solver.cleanups.load(Ordering::SeqCst), 1);
+  }
+
+  #[tokio::test]
+  async fn test_order_challenge_solver() {
+    let server = TestServer::start().await.unwrap();
+    let account =
+      test_server_account(&server, gen_ec_p256_private_key().unwrap()).await;
+
+    let mut builder = OrderBuilder::new(account);
+    let order = builder
+      .add_dns_identifier("solver.example.com".to_string())
+      .add_dns_identifier("*.solver.example.com".to_string())
+      .build()
+      .await
+      .unwrap();
+
+    order
+      .solve_challenges(&NoopSolver, Duration::from_millis(10), 3)
+      .await
+      .unwrap();
+
+    let order = order
+      .wait_ready(Duration::from_millis(10), 3)
+      .await
+      .unwrap();
+    assert_eq!(order.status, OrderStatus::Ready);
+  }
+
+  #[cfg(feature = "rfc2136")]
+  fn rfc2136_test_key() -> TsigKey {
+    TsigKey::from_base64(
+      "acme2-test.".to_string(),
+      TsigAlgorithm::HmacSha256,
+      "c2VjcmV0LWtleS1mb3ItYWNtZTItcmZjMjEzNi10ZXN0cw==",
+    )
+    .unwrap()
+  }
+
+  #[cfg(feature = "rfc2136")]
+  #[tokio::test]
+  async fn test_rfc2136_provider() {
+    use crate::dns_wire::encode_name;
+    use crate::rfc2136::sign;
+    use tokio::net::UdpSocket;
+
+    // A DNS server that answers the first update with NOTAUTH, and the
+    // second one with an unsigned NOERROR.
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let handle = tokio::spawn(async move {
+      let mut requests = vec![];
+      for rcode in [9u8, 0].iter() {
+        let mut buf = vec![0; 4096];
+        let (len, peer) = server.recv_from(&mut buf).await.unwrap();
+        buf.truncate(len);
+        let mut response = buf[..2].to_vec();
+        response.extend_from_slice(&[0xa8, *rcode, 0, 0, 0, 0, 0, 0, 0, 0]);
+        server.send_to(&response, peer).await.unwrap();
+        requests.push(buf);
+      }
+      requests
+    });
+
+    let key = rfc2136_test_key();
+    let provider =
+      Rfc2136ProviderBuilder::new(addr, "Example.com.".to_string())
+        .tsig_key(key.clone())
+        .build()
+        .unwrap();
+
+    let err = provider
+      .set_txt_record("_acme-challenge.other.org", "value")
+      .await
+      .unwrap_err();
+    assert!(matches!(err, Error::Validation(_)));
+
+    let err = provider
+      .set_txt_record("_acme-challenge.www.example.com", "value")
+      .await
+      .unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "the DNS server rejected the update: NOTAUTH"
+    );
+    let err = provider
+      .remove_txt_record("_acme-challenge.www.example.com", "value")
+      .await
+      .unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "transport error: DNS response is not signed"
+    );
+
+    let requests = handle.await.unwrap();
+    for (request, (class, ttl)) in requests
+      .iter()
+      .zip([([0, 1], [0, 0, 0, 60]), ([0, 254], [0, 0, 0, 0])].iter())
+    {
+      let mut expected = request[..2].to_vec();
+      expected.extend_from_slice(&[0x28, 0, 0, 1, 0, 0, 0, 1, 0, 0]);
+      encode_name(&mut expected, "example.com").unwrap();
+      expected.extend_from_slice(&[0, 6, 0, 1]);
+      encode_name(&mut expected, "_acme-challenge.www.example.com").unwrap();
+      expected.extend_from_slice(&[0, 16]);
+      expected.extend_from_slice(class);
+      expected.extend_from_slice(ttl);
+      expected.extend_from_slice(&[0, 6, 5]);
+      expected.extend_from_slice(b"value");
+
+      // Skip the key name, TSIG type, class, TTL, RDLENGTH, and algorithm
+      // name to get to the time the request was signed.
+      let offset = expected.len() + 12 + 10 + 13;
+      let mut time_signed = [0; 8];
+      time_signed[2..].copy_from_slice(&request[offset..offset + 6]);
+      sign(&mut expected, &key, u64::from_be_bytes(time_signed)).unwrap();
+      assert_eq!(&expected, request);
+    }
+  }
+
+  #[cfg(feature = "rfc2136")]
+  #[test]
+  fn test_rfc2136_response_record_counts() {
+    // An UPDATE response that claims 0xffff prerequisite and update records,
+    // without containing any.
+    let mut response = vec![0x12, 0x34, 0xa8, 0x00, 0, 0];
+    response.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0, 0]);
+    let err =
+      crate::rfc2136::verify_response(&response, 0x1234, None).unwrap_err();
+    assert!(matches!(err, Error::Transport(_)));
+  }
+
+  #[cfg(feature = "rfc2136")]
+  #[tokio::test]
+  #[ignore = "requires BIND, see docker-compose.yml"]
+  async fn test_rfc2136_provider_bind() {
+    let provider = Rfc2136ProviderBuilder::new(
+      "127.0.0.1:5353".parse().unwrap(),
+      "acme.test".to_string(),
+    )
+    .tsig_key(rfc2136_test_key())
+    .build()
+    .unwrap();
+
+    provider
+      .set_txt_record("_acme-challenge.www.acme.test", "value")
+      .await
+      .unwrap();
+    provider
+      .remove_txt_record("_acme-challenge.www.acme.test", "value")
+      .await
+      .unwrap();
+  }
+
+  /// A DNS server that answers every query with the records returned by
+  /// `handler` for the queried name and type, as (rcode, answers,
+  /// authorities).
+  #[cfg(any(feature = "rfc2136", feature = "dns-propagation"))]
+  async fn mock_dns_server<F>(handler: F) -> std::net::SocketAddr
+  where
+    F: Fn(&str, u16) -> (u8, Vec<Vec<u8>>, Vec<Vec<u8>>) + Send + 'static,
+  {
+    use crate::dns_wire::encode_name;
+    use crate::dns_wire::Reader;
+    use tokio::net::UdpSocket;
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+      let mut buf = vec![0; 512];
+      loop {
+        let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+        let mut reader = Reader::new(&buf[..len]);
+        reader.bytes(12).unwrap();
+        let name = reader.name().unwrap();
+        let r#type = reader.u16().unwrap();
+
+        let (rcode, answers, authorities) = handler(&name, r#type);
+        let mut response = buf[..2].to_vec();
+        response.extend_from_slice(&[0x84, rcode, 0, 1, 0]);
+        response.push(answers.len() as u8);
+        response.push(0);
+        response.push(authorities.len() as u8);
+        response.extend_from_slice(&[0, 0]);
+        encode_name(&mut response, &name).unwrap();
+        response.extend_from_slice(&r#type.to_be_bytes());
+        response.extend_from_slice(&[0, 1]);
+        for record in answers.iter().chain(authorities.iter()) {
+          response.extend_from_slice(record);
+        }
+        socket.send_to(&response, peer).await.unwrap();
+      }
+    });
+    addr
+  }
+
+  #[cfg(feature = "dns-propagation")]
+  #[tokio::test]
+  async fn test_dns_propagation_check() {
+    use crate::dns_wire::encode_name;
+    use crate::dns_wire::encode_record;
+    use crate::dns_wire::encode_txt;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    fn record(name: &str, r#type: u16, rdata: &[u8]) -> Vec<u8> {
+      let mut buf = vec![];
+      encode_record(&mut buf, name, r#type, 1, 60, rdata).unwrap();
+      buf
+    }
+
+    let resolver = mock_dns_server(|name, r#type| {
+      let mut soa = vec![];
+      encode_name(&mut soa, "ns1.example.com").unwrap();
+      encode_name(&mut soa, "hostmaster.example.com").unwrap();
+      soa.extend_from_slice(&[0; 20]);
+      let mut ns1 = vec![];
+      encode_name(&mut ns1, "ns1.example.com").unwrap();
+      let mut ns2 = vec![];
+      encode_name(&mut ns2, "ns2.example.net").unwrap();
+      let mut ipv6 = [0; 16];
+      ipv6[15] = 1;
+
+      match (name, r#type) {
+        ("example.com", 6) => (0, vec![record("example.com", 6, &soa)], vec![]),
+        ("example.com", 2) => (
+          0,
+          vec![
+            record("example.com", 2, &ns1),
+            record("example.com", 2, &ns2),
+          ],
+          vec![],
+        ),
+        ("ns1.example.com", 1) => (
+          0,
+          vec![record("ns1.example.com", 1, &[127, 0, 0, 1])],
+          vec![],
+        ),
+        ("ns1.example.com", 28) => {
+          (0, vec![record("ns1.example.com", 28, &ipv6)], vec![])
+        }
+        // The out of zone nameserver can not be resolved.
+        ("ns2.example.net", _) => (2, vec![], vec![]),
+        (_, 6) => (3, vec![], vec![record("example.com", 6, &soa)]),
+        _ => (0, vec![], vec![]),
+      }
+    })
+    .await;
+
+    let mut check = DnsPropagationCheck::new();
+    check.resolvers(vec![resolver]);
+    assert_eq!(
+      check
+        .authoritative_nameservers("_acme-challenge.www.example.com")
+        .await
+        .unwrap(),
+      vec![crate::dns_propagation::Nameserver {
+        host: "ns1.example.com".to_string(),
+        addrs: vec![
+          "127.0.0.1:53".parse().unwrap(),
+          "[::1]:53".parse().unwrap()
+        ],
+      }]
+    );
+
+    // A nameserver that only serves the record from the third query on.
+    let queries = Arc::new(AtomicUsize::new(0));
+    let counter = queries.clone();
+    let nameserver = mock_dns_server(move |name, _| {
+      if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+        return (0, vec![], vec![]);
+      }
+      (0, vec![record(name, 16, &encode_txt("value"))], vec![])
+    })
+    .await;
+
+    check
+      .nameservers(vec![nameserver])
+      .poll_interval(Duration::from_millis(10))
+      .timeout(Duration::from_secs(5));
+    check
+      .wait("_acme-challenge.www.example.com", "value")
+      .await
+      .unwrap();
+    assert_eq!(queries.load(Ordering::SeqCst), 3);
+
+    // A nameserver counts as propagated once any of its addresses serves
+    // the record, even if another one never answers.
+    let unreachable = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let nameserver = crate::dns_propagation::Nameserver {
+      host: "ns1.example.com".to_string(),
+      addrs: vec![unreachable.local_addr().unwrap(), nameserver],
+    };
+    check.query_timeout(Duration::from_millis(50));
+    check
+      .wait_for(
+        vec![nameserver],
+        "_acme-challenge.www.example.com",
+        "value",
+        tokio::time::Instant::now() + Duration::from_secs(5),
+      )
+      .await
+      .unwrap();
+
+    check.timeout(Duration::from_millis(50));
+    let err = check
+      .wait("_acme-challenge.www.example.com", "other")
+      .await
+      .unwrap_err();
+    assert!(
+      matches!(err, Error::DnsPropagationTimeout(name) if name == "_acme-challenge.www.example.com")
+    );
+  }
+
+  #[tokio::test]
+  async fn test_webroot_solver() {
+    let mut suffix = [0; 8];
+    openssl::rand::rand_bytes(&mut suffix).unwrap();
+    let webroot = std::env::temp_dir()
+      .join(format!("acme2-webroot-{}", crate::helpers::b64(&suffix)));
+
+    let account = offline_account(json!({}));
+    let mut authorization: Authorization = serde_json::from_value(json!({
+      "identifier": { "type": "dns", "value": "example.com" },
+      "status": "pending",
+      "challenges": [{
+        "type": "http-01",
+        "url": "https://example.com/acme/chall/1",
+        "status": "pending",
+        "token": "LoqXcYV8q5ONbJQxbmR7SCTNo3tiAXDfowyjxAjEuX0"
+      }]
+    }))
+    .unwrap();
+    authorization.challenges[0].account = Some(account);
+    let challenge = authorization.get_challenge("http-01").unwrap();
+    let key_authorization = challenge.key_authorization().unwrap().unwrap();
+
+    let solver = WebrootSolver::new(webroot.clone());
+    let path = solver.challenge_path(&challenge).unwrap();
+    assert_eq!(
+      path,
+      webroot.join(".well-known/acme-challenge/LoqXcYV8q5ONbJQxbmR7SCTNo3tiAXDfowyjxAjEuX0")
+    );
+
+    solver.present(&authorization, &challenge).await.unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), key_authorization);
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+      assert_eq!(mode & 0o777, 0o644);
+    }
+    let dir = path.parent().unwrap();
+    assert_eq!(std::fs::read_dir(dir).unwrap().count(), 1);
+
+    // Presenting the same challenge again keeps the file in place.
+    solver.present(&authorization, &challenge).await.unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), key_authorization);
+    assert_eq!(std::fs::read_dir(dir).unwrap().count(), 1);
+
+    solver.cleanup(&authorization, &challenge).await.unwrap();
+    assert!(!path.exists());
+
+    // Files that were not cleaned up are removed with the solver.
+    solver.present(&authorization, &challenge).await.unwrap();
+    assert!(path.exists());
+    drop(solver);
+    assert!(!path.exists());
+
+    let solver = WebrootSolver::new(webroot.clone());
+    let mut unsafe_challenge = challenge.clone();
+    for token in ["../../etc/passwd", "", "a.b", "a/b"].iter() {
+      unsafe_challenge.token = Some(token.to_string());
+      assert!(solver.challenge_path(&unsafe_challenge).is_err());
+      assert!(solver
+        .present(&authorization, &unsafe_challenge)
+        .await
+        .is_err());
+    }
+
+    std::fs::remove_dir_all(&webroot).unwrap();
+  }
+
+  /// A minimal HTTP/1.1 server that answers every request with the response
+  /// returned by `handler` for the request path and Host header.
+  fn mock_http_server(
+    handler: impl Fn(&str, &str) -> String + Send + 'static,
+  ) -> u16 {
+    use std::io::BufRead;
+    use std::io::Write;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+      for stream in listener.incoming() {
+        let mut stream = match stream {
+          Ok(stream) => stream,
+          Err(_) => continue,
+        };
+        let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let path = request_line.split(' ').nth(1).unwrap_or("").to_string();
+        let mut host = String::new();
+        loop {
+          let mut line = String::new();
+          reader.read_line(&mut line).unwrap();
+          if line.trim().is_empty() {
+            break;
+          }
+          if let Some(value) = line.to_lowercase().strip_prefix("host:") {
+            host = value.trim().to_string();
+          }
+        }
+        let response = handler(&path, &host);
+        stream.write_all(response.as_bytes()).unwrap();
+      }
+    });
+    port
+  }
+
+  #[tokio::test]
+  async fn test_http01_self_check() {
+    let account = offline_account(json!({}));
+    let mut challenge: Challenge = serde_json::from_value(json!({
+      "type": "http-01",
+      "url": "https://example.com/acme/chall/1",
+      "status": "pending",
+      "token": "LoqXcYV8q5ONbJQxbmR7SCTNo3tiAXDfowyjxAjEuX0"
+    }))
+    .unwrap();
+    challenge.account = Some(account);
+    challenge.identifier = Some(Identifier {
+      r#type: "dns".to_string(),
+      value: "example.com".to_string(),
+    });
+    let key_authorization = challenge.key_authorization().unwrap().unwrap();
+
+    let body = key_authorization.clone();
+    let port = mock_http_server(move |path, host| {
+      let host = host.split(':').next().unwrap();
+      let (status, location, body) = match path.rsplit('/').next().unwrap() {
+        "LoqXcYV8q5ONbJQxbmR7SCTNo3tiAXDfowyjxAjEuX0"
+          if host == "example.com" =>
+        {
+          ("302 Found", "/redirect", String::new())
+        }
+        "redirect" => ("301 Moved", "http://other.test/final", String::new()),
+        "final" if host == "other.test" => ("200 OK", "", body.clone()),
+        "bad-port" => ("302 Found", "http://other.test:1/final", String::new()),
+        "ftp" => ("302 Found", "ftp://other.test/final", String::new()),
+        _ => ("404 Not Found", "", String::new()),
+      };
+      let location = if location.is_empty() {
+        String::new()
+      } else {
+        format!("Location: {}\r\n", location)
+      };
+      format!(
+        "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        location,
+        body.len(),
+        body
+      )
+    });
+
+    let localhost = std::net::IpAddr::from([127, 0, 0, 1]);
+    let mut self_check = Http01SelfCheck::new();
+    self_check
+      .resolve("example.com".to_string(), localhost)
+      .resolve("other.test".to_string(), localhost)
+      .http_port(port);
+    self_check.check(&challenge).await.unwrap();
+
+    // Redirects to non-default ports or other schemes are not followed, and
+    // error statuses fail the check.
+    for path in ["bad-port", "ftp", "missing"].iter() {
+      let mut check = self_check.clone();
+      check.resolve(format!("{}.test", path), localhost);
+      let mut identifier = challenge.identifier.clone().unwrap();
+      identifier.value = format!("{}.test", path);
+      let mut other = challenge.clone();
+      other.identifier = Some(identifier);
+      other.token = Some(path.to_string());
+      let err = check.check(&other).await.unwrap_err();
+      assert!(matches!(err, Error::Http01SelfCheckFailed(_)), "{}", err);
+    }
+
+    // A wrong body fails the check.
+    let port = mock_http_server(|_, _| {
+      "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nwrong"
+        .to_string()
+    });
+    let mut check = self_check.clone();
+    check.http_port(port);
+    let err = check.check(&challenge).await.unwrap_err();
+    assert!(matches!(err, Error::Http01SelfCheckFailed(_)), "{}", err);
+  }
+
+  #[test]
+  fn test_challenge_policy() {
+    let authorization = |value: &str, wildcard: bool, types: &[&str]| {
+      let challenges: Vec<_> = types
+        .iter()
+        .enumerate()
+        .map(|(i, r#type)| {
+          json!({
+            "type": r#type,
+            "url": format!("https://example.com/acme/chall/{}", i),
+            "status": "pending",
+            "token": "LoqXcYV8q5ONbJQxbmR7SCTNo3tiAXDfowyjxAjEuX0"
+          })
+        })
+        .collect();
+      serde_json::from_value::<Authorization>(json!({
+        "identifier": { "type": "dns", "value": value },
+        "status": "pending",
+        "challenges": challenges,
+        "wildcard": wildcard
+      }))
+      .unwrap()
+    };
+    let all = &["http-01", "dns-01", "tls-alpn-01"];
+
+    let mut policy = ChallengePolicy::new(&["tls-alpn-01", "http-01"]);
+    policy
+      .wildcard(&["dns-01"])
+      .rule("*.internal", &["http-01"])
+      .rule("Example.org.", &["dns-01", "http-01"]);
+
+    let select = |value, wildcard, types| {
+      policy
+        .select(&authorization(value, wildcard, types))
+        .map(|challenge| challenge.r#type)
+    };
+    assert_eq!(select("example.com", false, all).unwrap(), "tls-alpn-01");
+    assert_eq!(
+      select("example.com", false, &["http-01", "dns-01"]).unwrap(),
+      "http-01"
+    );
+    assert_eq!(select("example.com", true, &["dns-01"]).unwrap(), "dns-01");
+    assert_eq!(select("a.b.internal", false, all).unwrap(), "http-01");
+    assert_eq!(select("example.org", false, all).unwrap(), "dns-01");
+    // The pattern only matches subdomains.
+    assert_eq!(select("internal", false, all).unwrap(), "tls-alpn-01");
+    assert_eq!(select("notinternal", false, all).unwrap(), "tls-alpn-01");
+
+    match select("host.internal", false, &["dns-01", "tls-alpn-01"]) {
+      Err(Error::NoAcceptableChallenge {
+        identifier,
+        offered,
+      }) => {
+        assert_eq!(identifier, "host.internal");
+        assert_eq!(offered, vec!["dns-01", "tls-alpn-01"]);
+      }
+      res => panic!("unexpected result: {:?}", res),
+    }
+    let err = select("example.com", true, &["http-01"]).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "the ACME server did not offer an acceptable challenge for *.example.com (offered: http-01)"
+    );
+
+    // Types the solver does not support are skipped.
+    let challenge = policy
+      .select_supported(
+        &authorization("example.com", false, all),
+        Some(&["http-01"]),
+      )
+      .unwrap();
+    assert_eq!(challenge.r#type, "http-01");
+    assert!(policy
+      .select_supported(
+        &authorization("example.com", true, all),
+        Some(&["http-01"])
+      )
+      .is_err());
+  }
+
+  #[tokio::test]
+  async fn test_cname_delegation() {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default)]
+    struct RecordingProvider {
+      records: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl DnsProvider for RecordingProvider {
+      async fn set_txt_record(
+        &self,
+        name: &str,
+        _value: &str,
+      ) -> Result<(), Error> {
+        self.records.lock().unwrap().push(name.to_string());
+        Ok(())
+      }
+
+      async fn remove_txt_record(
+        &self,
+        name: &str,
+        _value: &str,
+      ) -> Result<(), Error> {
+        self.records.lock().unwrap().retain(|record| record != name);
+        Ok(())
+      }
+    }
+
+    let account = offline_account(json!({}));
+    let mut authorization: Authorization = serde_json::from_value(json!({
+      "identifier": { "type": "dns", "value": "example.com" },
+      "status": "pending",
+      "challenges": [{
+        "type": "dns-01",
+        "url": "https://example.com/acme/chall/1",
+        "status": "pending",
+        "token": "evaGxfADs6pSRb2LAv9IZf17Dt3juxGJ-PCt92wr-oA"
+      }],
+      "wildcard": true
+    }))
+    .unwrap();
+    authorization.challenges[0].account = Some(account);
+    let challenge = authorization.get_challenge("dns-01").unwrap();
+
+    let mut delegations = HashMap::new();
+    delegations.insert(
+      "_acme-challenge.example.com".to_string(),
+      "_acme-challenge.Example.org.".to_string(),
+    );
+    delegations.insert(
+      "_acme-challenge.example.org".to_string(),
+      "d420c923.auth.acme-dns.test".to_string(),
+    );
+    let provider = Arc::new(RecordingProvider::default());
+    let mut solver = Dns01Solver::new(provider.clone());
+    solver.cname_delegation(CnameDelegation::static_map(delegations.clone()));
+
+    solver.present(&authorization, &challenge).await.unwrap();
+    assert_eq!(
+      *provider.records.lock().unwrap(),
+      vec!["d420c923.auth.acme-dns.test"]
+    );
+    solver.cleanup(&authorization, &challenge).await.unwrap();
+    assert!(provider.records.lock().unwrap().is_empty());
+
+    // Cleanup removes the record that was created, even if the delegation
+    // changed in the meantime.
+    solver.present(&authorization, &challenge).await.unwrap();
+    let mut changed = delegations.clone();
+    changed.insert(
+      "_acme-challenge.example.org".to_string(),
+      "0b7f1e2a.auth.acme-dns.test".to_string(),
+    );
+    solver.cname_delegation(CnameDelegation::static_map(changed));
+    solver.cleanup(&authorization, &challenge).await.unwrap();
+    assert!(provider.records.lock().unwrap().is_empty());
+    solver.cname_delegation(CnameDelegation::static_map(delegations.clone()));
+
+    let delegation = CnameDelegation::static_map(delegations.clone());
+    assert_eq!(
+      delegation
+        .resolve("_acme-challenge.other.com.")
+        .await
+        .unwrap(),
+      "_acme-challenge.other.com"
+    );
+
+    delegations.insert(
+      "d420c923.auth.acme-dns.test".to_string(),
+      "_acme-challenge.example.com".to_string(),
+    );
+    let delegation = CnameDelegation::static_map(delegations);
+    let err = delegation
+      .resolve("_acme-challenge.example.com")
+      .await
+      .unwrap_err();
+    assert!(
+      matches!(err, Error::CnameLoop(name) if name == "_acme-challenge.example.com")
+    );
+    solver.cname_delegation(delegation);
+    assert!(solver.present(&authorization, &challenge).await.is_err());
+    assert!(provider.records.lock().unwrap().is_empty());
+  }
+
+  #[cfg(any(feature = "rfc2136", feature = "dns-propagation"))]
+  #[tokio::test]
+  async fn test_cname_delegation_live() {
+    use crate::dns_wire::encode_name;
+    use crate::dns_wire::encode_record;
+
+    fn cname(name: &str, target: &str) -> Vec<Vec<u8>> {
+      let mut rdata = vec![];
+      encode_name(&mut rdata, target).unwrap();
+      let mut buf = vec![];
+      encode_record(&mut buf, name, 5, 1, 60, &rdata).unwrap();
+      vec![buf]
+    }
+
+    let resolver = mock_dns_server(|name, _| match name {
+      "_acme-challenge.example.com" => {
+        (0, cname(name, "_acme-challenge.example.org"), vec![])
+      }
+      "_acme-challenge.example.org" => {
+        (0, cname(name, "d420c923.auth.acme-dns.test"), vec![])
+      }
+      "_acme-challenge.loop.test" => {
+        (0, cname(name, "_acme-challenge.loop.test"), vec![])
+      }
+      _ => (3, vec![], vec![]),
+    })
+    .await;
+
+    let delegation = CnameDelegation::live(vec![resolver]);
+    assert_eq!(
+      delegation
+        .resolve("_acme-challenge.example.com")
+        .await
+        .unwrap(),
+      "d420c923.auth.acme-dns.test"
+    );
+    assert_eq!(
+      delegation
+        .resolve("_acme-challenge.other.com")
+        .await
+        .unwrap(),
+      "_acme-challenge.other.com"
+    );
+    let err = delegation
+      .resolve("_acme-challenge.loop.test")
+      .await
+      .unwrap_err();
+    assert!(matches!(err, Error::CnameLoop(_)));
+  }
+
+  /// A solver that does not provision anything, for ACME servers that do
+  /// not actually validate challenges.
+  #[derive(Debug)]
+  struct NoopSolver;
+
+  #[async_trait]
+  impl ChallengeSolver for NoopSolver {
+    fn supported_types(&self) -> &[&str] {
+      &["http-01", "dns-01"]
+    }
+
+    async fn present(
+      &self,
+      _authorization: &Authorization,
+      _challenge: &Challenge,
+    ) -> Result<(), Error> {
+      Ok(())
+    }
+
+    async fn cleanup(
+      &self,
+      _authorization: &Authorization,
+      _challenge: &Challenge,
+    ) -> Result<(), Error> {
+      Ok(())
+    }
+  }
+
+  async fn test_server_directory(server: &TestServer) -> Arc<Directory> {
+    DirectoryBuilder::new(server.directory_url().to_string())
+      .build()
+      .await
+      .unwrap()
+  }
+
+  async fn test_server_account(
+    server: &TestServer,
+    private_key: openssl::pkey::PKey<openssl::pkey::Private>,
+  ) -> Arc<Account> {
+    AccountBuilder::new(test_server_directory(server).await)
+      .private_key(private_key)
+      .contact(vec!["mailto:hello@lcas.dev".to_string()])
+      .terms_of_service_agreed(true)
+      .build()
+      .await
+      .unwrap()
+  }
+
+  /// Create and finalize an order for `domain` on the test server.
+  async fn test_server_valid_order(
+    account: Arc<Account>,
+    domain: &str,
+  ) -> (openssl::pkey::PKey<openssl::pkey::Private>, Order) {
+    let mut builder = OrderBuilder::new(account);
+    let order = builder
+      .add_dns_identifier(domain.to_string())
+      .build()
+      .await
+      .unwrap();
+    order
+      .solve_challenges(&NoopSolver, Duration::from_millis(10), 3)
+      .await
+      .unwrap();
+
+    let order = order
+      .wait_ready(Duration::from_millis(10), 3)
+      .await
+      .unwrap();
+    let pkey = gen_ec_p256_private_key().unwrap();
+    let order = order.finalize(Csr::Automatic(pkey.clone())).await.unwrap();
+    let order = order.wait_done(Duration::from_millis(10), 3).await.unwrap();
+    assert_eq!(order.status, OrderStatus::Valid);
+
+    (pkey, order)
+  }
+
+  /// Issue a certificate for `domain` from the test server.
+  async fn test_server_certificate(
+    account: Arc<Account>,
+    domain: &str,
+  ) -> (
+    openssl::pkey::PKey<openssl::pkey::Private>,
+    Vec<openssl::x509::X509>,
+  ) {
+    let (pkey, order) = test_server_valid_order(account, domain).await;
+    (pkey, order.certificate().await.unwrap().unwrap())
+  }
+
+  #[tokio::test]
+  async fn test_test_server_issuance() {
+    use openssl::stack::Stack;
+    use openssl::x509::store::X509StoreBuilder;
+    use openssl::x509::X509StoreContext;
+
+    let server = TestServer::start().await.unwrap();
+
+    for key in [
+      gen_rsa_private_key(2048).unwrap(),
+      gen_ec_p384_private_key().unwrap(),
+      gen_ed25519_private_key().unwrap(),
+    ] {
+      let account = test_server_account(&server, key).await;
+      assert_eq!(account.status, AccountStatus::Valid);
+
+      let mut builder = OrderBuilder::new(account.clone());
+      let order = builder
+        .add_dns_identifier("test-server.example.com".to_string())
+        .add_dns_identifier("*.test-server.example.com".to_string())
+        .add_ip_identifier("192.0.2.1".parse().unwrap())
+        .build()
+        .await
+        .unwrap();
+      assert_eq!(order.status, OrderStatus::Pending);
+
+      // Finalizing an order before its challenges are solved fails.
+      let pkey = gen_ec_p256_private_key().unwrap();
+      let err = order
+        .finalize(Csr::Automatic(pkey.clone()))
+        .await
+        .unwrap_err();
+      match err {
+        Error::Server(err) => assert_eq!(
+          err.r#type.as_deref(),
+          Some("urn:ietf:params:acme:error:orderNotReady")
+        ),
+        err => panic!("unexpected error: {:?}", err),
+      }
+
+      order
+        .solve_challenges(&NoopSolver, Duration::from_millis(10), 3)
+        .await
+        .unwrap();
+      let order = order
+        .wait_ready(Duration::from_millis(10), 3)
+        .await
+        .unwrap();
+      assert_eq!(order.status, OrderStatus::Ready);
+
+      let order = order.finalize(Csr::Automatic(pkey.clone())).await.unwrap();
+      let order = order.wait_done(Duration::from_millis(10), 3).await.unwrap();
+      assert_eq!(order.status, OrderStatus::Valid);
+
+      let certs = order.certificate().await.unwrap().unwrap();
+      assert_eq!(certs.len(), 2);
+      assert!(certs[0].public_key().unwrap().public_eq(&pkey));
+      let names: Vec<_> = certs[0]
+        .subject_alt_names()
+        .unwrap()
+        .iter()
+        .map(|name| match name.dnsname() {
+          Some(dns) => dns.to_string(),
+          None => format!("{:?}", name.ipaddress().unwrap()),
+        })
+        .collect();
+      assert!(names.contains(&"test-server.example.com".to_string()));
+      assert!(names.contains(&"*.test-server.example.com".to_string()));
+      assert!(names.contains(&format!("{:?}", &[192u8, 0, 2, 1][..])));
+
+      let mut store = X509StoreBuilder::new().unwrap();
+      store.add_cert(server.root_certificate().clone()).unwrap();
+      let store = store.build();
+      let mut chain = Stack::new().unwrap();
+      chain.push(certs[1].clone()).unwrap();
+      let mut context = X509StoreContext::new().unwrap();
+      assert!(context
+        .init(&store, &certs[0], &chain, |c| c.verify_cert())
+        .unwrap());
+
+      let mut orders = account.orders().unwrap();
+      assert_eq!(orders.next().await.unwrap(), Some(order.url.clone()));
+      assert_eq!(orders.next().await.unwrap(), None);
+    }
+  }
+
+  #[tokio::test]
+  async fn test_test_server_lifecycle() {
+    let server = TestServer::start().await.unwrap();
+    let account =
+      test_server_account(&server, gen_ec_p256_private_key().unwrap()).await;
+    let dir = account.directory.clone().unwrap();
+
+    // A CSR that does not match the identifiers of the order is rejected.
+    let mut builder = OrderBuilder::new(account.clone());
+    let order = builder
+      .add_dns_identifier("lifecycle.example.com".to_string())
+      .build()
+      .await
+      .unwrap();
+    order
+      .solve_challenges(&NoopSolver, Duration::from_millis(10), 3)
+      .await
+      .unwrap();
+    let pkey = gen_ec_p256_private_key().unwrap();
+    let mut csr = openssl::x509::X509ReqBuilder::new().unwrap();
+    let mut names = openssl::stack::Stack::new().unwrap();
+    names
+      .push(
+        openssl::x509::extension::SubjectAlternativeName::new()
+          .dns("other.example.com")
+          .build(&csr.x509v3_context(None))
+          .unwrap(),
+      )
+      .unwrap();
+    csr.add_extensions(&names).unwrap();
+    csr.set_pubkey(&pkey).unwrap();
+    csr
+      .sign(&pkey, openssl::hash::MessageDigest::sha256())
+      .unwrap();
+    let err = order.finalize(Csr::Custom(csr.build())).await.unwrap_err();
+    match err {
+      Error::Server(err) => assert_eq!(
+        err.r#type.as_deref(),
+        Some("urn:ietf:params:acme:error:badCSR")
+      ),
+      err => panic!("unexpected error: {:?}", err),
+    }
+
+    let order = order.finalize(Csr::Automatic(pkey.clone())).await.unwrap();
+    let certs = order.certificate().await.unwrap().unwrap();
+
+    let info = dir.renewal_info(&certs[0]).await.unwrap();
+    assert!(info.suggested_window.start > SystemTime::now());
+    assert!(info.suggested_window.start < info.suggested_window.end);
+
+    account
+      .revoke_certificate(&certs[0], Some(RevocationReason::Superseded))
+      .await
+      .unwrap();
+    let err = dir
+      .revoke_certificate_with_key(&certs[0], &pkey, None)
+      .await
+      .unwrap_err();
+    match err {
+      Error::Server(err) => assert_eq!(
+        err.r#type.as_deref(),
+        Some("urn:ietf:params:acme:error:alreadyRevoked")
+      ),
+      err => panic!("unexpected error: {:?}", err),
+    }
+    let info = dir.renewal_info(&certs[0]).await.unwrap();
+    assert!(info.suggested_window.end < SystemTime::now());
+
+    let new_key = gen_ec_p256_private_key().unwrap();
+    let new_account = account.change_key(new_key.clone()).await.unwrap();
+    assert_eq!(new_account.id, account.id);
+    let existing = AccountBuilder::new(dir.clone())
+      .private_key(new_key)
+      .only_return_existing(true)
+      .build()
+      .await
+      .unwrap();
+    assert_eq!(existing.id, account.id);
+
+    let account = new_account.deactivate().await.unwrap();
+    assert_eq!(account.status, AccountStatus::Deactivated);
+  }
+
+  #[tokio::test]
+  async fn test_test_server_orders_pagination() {
+    let server = TestServerBuilder::new()
+      .orders_page_size(2)
+      .build()
+      .await
+      .unwrap();
+    let account =
+      test_server_account(&server, gen_ec_p256_private_key().unwrap()).await;
+
+    let mut order_urls = vec![];
+    for _ in 0..5 {
+      let mut builder = OrderBuilder::new(account.clone());
+      let order = builder
+        .add_dns_identifier("pagination.example.com".to_string())
+        .build()
+        .await
+        .unwrap();
+      order_urls.push(order.url);
+    }
+
+    let mut orders = account.orders().unwrap();
+    let mut listed = vec![];
+    while let Some(url) = orders.next().await.unwrap() {
+      listed.push(url);
+    }
+    assert_eq!(listed, order_urls);
+    assert_eq!(orders.next().await.unwrap(), None);
+  }
+
+  #[tokio::test]
+  async fn test_test_server_jws_errors() {
+    use crate::helpers::b64;
+
+    let server = TestServer::start().await.unwrap();
+    let base = server.directory_url().trim_end_matches("/directory");
+    let client = reqwest::Client::new();
+
+    // RFC 8555 section 7.2: HEAD is answered with 200, GET with 204.
+    let url = format!("{}/new-nonce", base);
+    let res = client.head(&url).send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert!(res.headers().contains_key("replay-nonce"));
+    let res = client.get(&url).send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::NO_CONTENT);
+
+    let pkey = gen_ec_p256_private_key().unwrap();
+    let url = format!("{}/new-account", base);
+    for (alg, expected) in
+      [("RS256", "badSignatureAlgorithm"), ("ES256", "malformed")]
+    {
+      let res = client.head(format!("{}/new-nonce", base)).send().await;
+      let nonce = res.unwrap().headers()["replay-nonce"]
+        .to_str()
+        .unwrap()
+        .to_string();
+      let header = json!({
+        "alg": alg,
+        "nonce": nonce,
+        "url": url,
+        "jwk": Jwk::new(&pkey).unwrap(),
+      });
+      let body = json!({
+        "protected": b64(header.to_string().as_bytes()),
+        "payload": b64(b"{}"),
+        "signature": b64(&[0; 64]),
+      });
+      let res = client
+        .post(&url)
+        .header("content-type", "application/jose+json")
+        .body(body.to_string())
+        .send()
+        .await
+        .unwrap();
+      assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+      let err: ServerError = res.json().await.unwrap();
+      assert_eq!(
+        err.r#type,
+        Some(format!("urn:ietf:params:acme:error:{}", expected))
+      );
+    }
+  }
+
+  #[tokio::test]
+  async fn test_test_server_validity_rejected() {
+    let server = TestServer::start().await.unwrap();
+    let account =
+      test_server_account(&server, gen_ec_p256_private_key().unwrap()).await;
+    let malformed = Some("urn:ietf:params:acme:error:malformed".to_string());
+
+    let now = SystemTime::now();
+    let err = OrderBuilder::new(account.clone())
+      .add_dns_identifier("example.com".to_string())
+      .not_before(now)
+      .not_after(now + Duration::from_secs(365 * 24 * 60 * 60))
+      .build()
+      .await
+      .unwrap_err();
+    assert!(
+      matches!(&err, Error::ValidityRejected(err) if err.r#type == malformed),
+      "{}",
+      err
+    );
+
+    // Malformed errors about other fields of the order are passed through,
+    // even if the order requested a validity.
+    let err = OrderBuilder::new(account.clone())
+      .not_before(now)
+      .build()
+      .await
+      .unwrap_err();
+    assert!(
+      matches!(&err, Error::Server(err) if err.r#type == malformed),
+      "{}",
+      err
+    );
+
+    // Without a requested validity, malformed errors are passed through.
+    let err = OrderBuilder::new(account).build().await.unwrap_err();
+    assert!(
+      matches!(&err, Error::Server(err) if err.r#type == malformed),
+      "{}",
+      err
+    );
+  }
+
+  /// A validator that checks http-01 challenges against an
+  /// [`Http01Responder`], and rejects all other challenges.
+  #[cfg(feature = "http01-responder")]
+  #[derive(Debug)]
+  struct ResponderValidator(Arc<Http01Responder>);
+
+  #[cfg(feature = "http01-responder")]
+  #[async_trait]
+  impl ChallengeValidator for ResponderValidator {
+    async fn validate(
+      &self,
+      request: &ValidationRequest,
+    ) -> Result<(), String> {
+      if request.r#type != "http-01" {
+        return Err(format!("{} is not supported", request.r#type));
+      }
+      match self.0.key_authorization(&request.token) {
+        Some(key_authorization)
+          if key_authorization == request.key_authorization =>
+        {
+          Ok(())
+        }
+        _ => Err("the key authorization was not found".to_string()),
+      }
+    }
+  }
+
+  #[cfg(feature = "http01-responder")]
+  #[tokio::test]
+  async fn test_test_server_validator() {
+    let responder = Arc::new(Http01Responder::new());
+    let server = TestServerBuilder::new()
+      .validator(Arc::new(ResponderValidator(responder.clone())))
+      .build()
+      .await
+      .unwrap();
+    let account =
+      test_server_account(&server, gen_ec_p256_private_key().unwrap()).await;
+
+    let mut builder = OrderBuilder::new(account.clone());
+    let order = builder
+      .add_dns_identifier("validator.example.com".to_string())
+      .build()
+      .await
+      .unwrap();
+    let auth = order.authorizations().await.unwrap().remove(0);
+    let challenge = auth.get_challenge("http-01").unwrap();
+    responder.register(&challenge).unwrap();
+    let challenge = challenge.validate().await.unwrap();
+    let challenge = challenge
+      .wait_done(Duration::from_millis(10), 3)
+      .await
+      .unwrap();
+    assert_eq!(challenge.status, ChallengeStatus::Valid);
+    let order = order
+      .wait_ready(Duration::from_millis(10), 3)
+      .await
+      .unwrap();
+    assert_eq!(order.status, OrderStatus::Ready);
+
+    // The validator does not accept dns-01 challenges.
+    let mut builder = OrderBuilder::new(account);
+    let order = builder
+      .add_dns_identifier("validator.example.com".to_string())
+      .build()
+      .await
+      .unwrap();
+    let auth = order.authorizations().await.unwrap().remove(0);
+    let challenge = auth.get_challenge("dns-01").unwrap();
+    let challenge = challenge.validate().await.unwrap();
+    let challenge = challenge
+      .wait_done(Duration::from_millis(10), 3)
+      .await
+      .unwrap();
+    assert_eq!(challenge.status, ChallengeStatus::Invalid);
+    assert_eq!(
+      challenge.error.unwrap().detail.as_deref(),
+      Some("dns-01 is not supported")
+    );
+    let order = order.poll().await.unwrap();
+    assert_eq!(order.status, OrderStatus::Invalid);
+  }
+
+  #[tokio::test]
+  async fn test_key_change_jws() {
+    let old_key = gen_rsa_private_key(2048).unwrap();
+    let new_key = gen_ec_p256_private_key().unwrap();
+
+    let inner = crate::jws::key_change_jws(
+      "https://example.com/acme/key-change",
+      "https://example.com/acme/acct/1",
+      &Jwk::new(&old_key).unwrap(),
+      &new_key,
+    )
+    .await
+    .unwrap();
+
+    let decode = |field: &str| {
+      base64::decode_config(
+        inner[field].as_str().unwrap(),
+        base64::URL_SAFE_NO_PAD,
+      )
+      .unwrap()
+    };
+
+    let header: serde_json::Value =
+      serde_json::from_slice(&decode("protected")).unwrap();
+    assert_eq!(header["alg"], "ES256");
+    assert_eq!(header["url"], "https://example.com/acme/key-change");
+    assert_eq!(
+      header["jwk"],
+      serde_json::to_value(Jwk::new(&new_key).unwrap()).unwrap()
+    );
+    assert!(header.get("nonce").is_none());
+    assert!(header.get("kid").is_none());
+
+    let payload: serde_json::Value =
+      serde_json::from_slice(&decode("payload")).unwrap();
+    assert_eq!(payload["account"], "https://example.com/acme/acct/1");
+    assert_eq!(
+      payload["oldKey"],
+      serde_json::to_value(Jwk::new(&old_key).unwrap()).unwrap()
+    );
+  }
+
+  #[tokio::test]
+  async fn test_account_key_change() {
+    let server = TestServer::start().await.unwrap();
+    let account =
+      test_server_account(&server, gen_rsa_private_key(2048).unwrap()).await;
+    let new_key = gen_ec_p256_private_key().unwrap();
+
+    let new_account = account.change_key(new_key.clone()).await.unwrap();
+    assert_eq!(new_account.id, account.id);
+    assert!(new_account.private_key().unwrap().public_eq(&new_key));
+
+    // The new key must resolve to the same account.
+    let mut builder = AccountBuilder::new(account.directory.clone().unwrap());
+    let existing = builder
+      .private_key(new_key)
+      .only_return_existing(true)
+      .build()
+      .await
+      .unwrap();
+    assert_eq!(existing.id, account.id);
+  }
+
+  #[test]
+  fn test_jwk_ed25519_thumbprint() {
+    use openssl::pkey::Id;
+    use openssl::pkey::PKey;
+
+    // Test vector from RFC 8037, appendix A.
+    let d = base64::decode_config(
+      "nWGxne_9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A",
+      base64::URL_SAFE_NO_PAD,
+    )
+    .unwrap();
+    let pkey = PKey::private_key_from_raw_bytes(&d, Id::ED25519).unwrap();
+    let jwk = crate::jws::Jwk::new(&pkey).unwrap();
+
+    assert_eq!(
+      serde_json::to_string(&jwk).unwrap(),
+      r#"{"crv":"Ed25519","kty":"OKP","x":"11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"}"#
+    );
+    assert_eq!(
+      jwk.thumbprint().unwrap(),
+      "kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k"
+    );
+  }
+
+  #[tokio::test]
+  #[ignore = "requires Pebble, see docker-compose.yml"]
   async fn test_order_http01_challenge_pebble_rsa() {
     let account = pebble_account().await;
 
@@ -298,6 +2860,7 @@ mod tests {
   }
 
   #[tokio::test]
+  #[ignore = "requires Pebble, see docker-compose.yml"]
   async fn test_order_http01_challenge_pebble_ec() {
     let account = pebble_account().await;
 
@@ -367,6 +2930,7 @@ mod tests {
   }
 
   #[tokio::test]
+  #[ignore = "requires Pebble, see docker-compose.yml"]
   async fn test_order_dns01_challenge_pebble() {
     let account = pebble_account().await;
     let mut builder = OrderBuilder::new(account);
@@ -2,6 +2,7 @@ use crate::account::Account;
 use crate::error::*;
 use crate::helpers::*;
 use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
 use openssl::pkey::PKey;
 use openssl::pkey::Private;
 use openssl::stack::Stack;
@@ -11,8 +12,12 @@ use openssl::x509::X509Req;
 use openssl::x509::X509;
 use serde::Deserialize;
 use serde_json::json;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::net::IpAddr;
 use std::sync::Arc;
 use std::time::Duration;
+use std::time::SystemTime;
 use tracing::debug;
 use tracing::field;
 use tracing::instrument;
@@ -56,6 +61,12 @@ pub struct Order {
   pub not_before: Option<String>,
   /// The requested value of the notAfter field in the certificate.
   pub not_after: Option<String>,
+  /// The ARI certificate identifier of the certificate this order replaces,
+  /// if any.
+  pub replaces: Option<String>,
+  /// The name of the certificate profile this order was created with, if
+  /// any.
+  pub profile: Option<String>,
 
   /// The error that occurred while processing the order, if any.
   pub error: Option<ServerError>,
@@ -82,7 +93,10 @@ pub struct OrderBuilder {
   account: Arc<Account>,
 
   identifiers: Vec<Identifier>,
-  // TODO(lucacasonato): externalAccountBinding
+  replaces: Option<String>,
+  profile: Option<String>,
+  not_before: Option<SystemTime>,
+  not_after: Option<SystemTime>,
 }
 
 impl OrderBuilder {
@@ -90,12 +104,17 @@ impl OrderBuilder {
     OrderBuilder {
       account,
       identifiers: vec![],
+      replaces: None,
+      profile: None,
+      not_before: None,
+      not_after: None,
     }
   }
 
   /// Set the identifiers for an order.
   ///
-  /// In most cases, you can use [`OrderBuilder::add_dns_identifier`]
+  /// In most cases, you can use [`OrderBuilder::add_dns_identifier`] or
+  /// [`OrderBuilder::add_ip_identifier`].
   pub fn set_identifiers(&mut self, identifiers: Vec<Identifier>) -> &mut Self {
     self.identifiers = identifiers;
     self
@@ -104,31 +123,120 @@ impl OrderBuilder {
   /// Add a type `dns` identifier to the list of identifiers for this
   /// order.
   pub fn add_dns_identifier(&mut self, fqdn: String) -> &mut Self {
-    self.identifiers.push(Identifier {
-      r#type: "dns".to_string(),
-      value: fqdn,
-    });
+    self.identifiers.push(Identifier::dns(fqdn));
+    self
+  }
+
+  /// Add a type `ip` identifier to the list of identifiers for this
+  /// order (RFC 8738). Not all ACME servers support IP identifiers.
+  pub fn add_ip_identifier(&mut self, ip: IpAddr) -> &mut Self {
+    self.identifiers.push(Identifier::ip(ip));
+    self
+  }
+
+  /// Mark this order as a renewal of an existing certificate, using the ARI
+  /// certificate identifier of that certificate (see
+  /// [`crate::certificate_identifier`]).
+  ///
+  /// This lets the ACME server know that the old certificate is being
+  /// replaced, which may exempt the order from rate limits during events
+  /// like mass revocations.
+  pub fn replaces(&mut self, certificate_id: String) -> &mut Self {
+    self.replaces = Some(certificate_id);
+    self
+  }
+
+  /// Request a certificate profile for this order, for example
+  /// `"shortlived"`. The available profiles are listed in
+  /// [`crate::DirectoryMeta::profiles`].
+  ///
+  /// The profile is validated against the directory when the order is
+  /// built.
+  pub fn profile(&mut self, profile: String) -> &mut Self {
+    self.profile = Some(profile);
+    self
+  }
+
+  /// Request a specific start of the validity period of the certificate
+  /// (the `notBefore` field).
+  ///
+  /// Not all ACME servers support this. See [`Error::ValidityRejected`] for
+  /// how a rejected validity is reported.
+  pub fn not_before(&mut self, not_before: SystemTime) -> &mut Self {
+    self.not_before = Some(not_before);
+    self
+  }
+
+  /// Request a specific end of the validity period of the certificate (the
+  /// `notAfter` field).
+  ///
+  /// Not all ACME servers support this. See [`Error::ValidityRejected`] for
+  /// how a rejected validity is reported.
+  pub fn not_after(&mut self, not_after: SystemTime) -> &mut Self {
+    self.not_after = Some(not_after);
     self
   }
 
   /// This will request a new [`Order`] from the ACME server.
-  #[instrument(level = Level::INFO, name = "acme2::OrderBuilder::build", err, skip(self), fields(identifiers = ?self.identifiers, order_url = field::Empty))]
+  #[instrument(level = Level::INFO, name = "acme2::OrderBuilder::build", err, skip(self), fields(identifiers = ?self.identifiers, replaces = ?self.replaces, profile = ?self.profile, not_before = ?self.not_before, not_after = ?self.not_after, order_url = field::Empty))]
   pub async fn build(&mut self) -> Result<Order, Error> {
     let dir = self.account.directory.clone().unwrap();
 
+    if let Some(profile) = &self.profile {
+      let profiles = dir
+        .meta
+        .as_ref()
+        .and_then(|meta| meta.profiles.as_ref())
+        .ok_or(Error::Validation(
+          "the ACME server does not support certificate profiles",
+        ))?;
+      if !profiles.contains_key(profile) {
+        return Err(Error::Validation(
+          "the ACME server does not offer the requested certificate profile",
+        ));
+      }
+    }
+
+    let mut payload = json!({
+      "identifiers": self.identifiers,
+    });
+    if let Some(replaces) = &self.replaces {
+      payload["replaces"] = json!(replaces);
+    }
+    if let Some(profile) = &self.profile {
+      payload["profile"] = json!(profile);
+    }
+    if let (Some(not_before), Some(not_after)) =
+      (self.not_before, self.not_after)
+    {
+      if not_after <= not_before {
+        return Err(Error::Validation("notAfter must be later than notBefore"));
+      }
+    }
+    if let Some(not_before) = self.not_before {
+      payload["notBefore"] = json!(format_rfc3339(not_before)?);
+    }
+    if let Some(not_after) = self.not_after {
+      payload["notAfter"] = json!(format_rfc3339(not_after)?);
+    }
+
     let (res, headers) = dir
       .authenticated_request::<_, Order>(
         &dir.new_order_url,
-        json!({
-          "identifiers": self.identifiers,
-        }),
-        self.account.private_key.clone().unwrap(),
+        payload,
+        &*self.account.signer(),
         Some(self.account.id.clone()),
       )
       .await?;
 
-    let res: Result<Order, Error> = res.into();
-    let mut order = res?;
+    let custom_validity = self.not_before.is_some() || self.not_after.is_some();
+    let mut order = match res {
+      ServerResult::Ok(order) => order,
+      ServerResult::Err(err) if custom_validity && is_validity_error(&err) => {
+        return Err(Error::ValidityRejected(err))
+      }
+      ServerResult::Err(err) => return Err(err.into()),
+    };
 
     let order_url = map_transport_err(
       headers
@@ -141,7 +249,7 @@ impl OrderBuilder {
         .to_str(),
     )?
     .to_string();
-    Span::current().record("order_url", &field::display(&order_url));
+    Span::current().record("order_url", field::display(&order_url));
 
     order.account = Some(self.account.clone());
     order.url = order_url;
@@ -150,6 +258,165 @@ impl OrderBuilder {
   }
 }
 
+#[derive(Deserialize)]
+struct OrderList {
+  orders: Vec<String>,
+}
+
+/// A paginated list of the URLs of all orders created by an [`Account`].
+///
+/// This must be created through [`Account::orders`]. Use
+/// [`AccountOrders::next`] to iterate over the order URLs, and
+/// [`Account::order`] to retrieve the full [`Order`] for a URL.
+#[derive(Debug)]
+pub struct AccountOrders {
+  account: Arc<Account>,
+  next_page_url: Option<String>,
+  fetched_page_urls: HashSet<String>,
+  order_urls: VecDeque<String>,
+}
+
+impl AccountOrders {
+  /// Get the URL of the next order in the list, or `None` if there are no
+  /// more orders. Further pages of the list are fetched from the ACME
+  /// server as needed.
+  ///
+  /// If the ACME server links back to a page that was already fetched, a
+  /// transport error is returned instead of following the loop.
+  #[instrument(level = Level::DEBUG, name = "acme2::AccountOrders::next", err, skip(self), fields(account_id = %self.account.id))]
+  pub async fn next(&mut self) -> Result<Option<String>, Error> {
+    while self.order_urls.is_empty() {
+      let url = match self.next_page_url.take() {
+        Some(url) => url,
+        None => return Ok(None),
+      };
+      if !self.fetched_page_urls.insert(url.clone()) {
+        return Err(transport_err(
+          "the orders list of the ACME server links to a page twice",
+        ));
+      }
+
+      let directory = self.account.directory.clone().unwrap();
+      let (res, headers) = directory
+        .authenticated_request::<_, OrderList>(
+          &url,
+          json!(""),
+          &*self.account.signer(),
+          Some(self.account.id.clone()),
+        )
+        .await?;
+      let res: Result<OrderList, Error> = res.into();
+      let list = res?;
+
+      debug!({ count = list.orders.len() }, "Fetched page of orders.");
+      self.order_urls.extend(list.orders);
+      self.next_page_url =
+        extract_links(&headers, &url, "next").into_iter().next();
+    }
+
+    Ok(self.order_urls.pop_front())
+  }
+}
+
+impl Account {
+  /// List the URLs of all orders created by this account.
+  ///
+  /// Not all ACME servers support this. If the server did not provide an
+  /// orders URL for this account, a validation error is returned.
+  pub fn orders(self: &Arc<Self>) -> Result<AccountOrders, Error> {
+    let orders_url = self.orders_url.clone().ok_or(Error::Validation(
+      "the ACME server does not provide an orders list for this account",
+    ))?;
+
+    Ok(AccountOrders {
+      account: self.clone(),
+      next_page_url: Some(orders_url),
+      fetched_page_urls: HashSet::new(),
+      order_urls: VecDeque::new(),
+    })
+  }
+
+  /// Retrieve an existing [`Order`] by its URL, for example one returned by
+  /// [`Account::orders`].
+  #[instrument(level = Level::INFO, name = "acme2::Account::order", err, skip(self), fields(status = field::Empty))]
+  pub async fn order(self: &Arc<Self>, url: String) -> Result<Order, Error> {
+    let directory = self.directory.clone().unwrap();
+
+    let (res, _) = directory
+      .authenticated_request::<_, Order>(
+        &url,
+        json!(""),
+        &*self.signer(),
+        Some(self.id.clone()),
+      )
+      .await?;
+    let res: Result<Order, Error> = res.into();
+    let mut order = res?;
+    Span::current().record("status", field::debug(&order.status));
+    order.account = Some(self.clone());
+    order.url = url;
+    Ok(order)
+  }
+}
+
+/// ACME servers reject orders with a `notBefore` / `notAfter` they do not
+/// support (or do not accept) as malformed. There is no dedicated error
+/// type for this, so only errors whose detail names one of the fields are
+/// known to be about the validity.
+fn is_validity_error(err: &ServerError) -> bool {
+  if err.r#type.as_deref() != Some("urn:ietf:params:acme:error:malformed") {
+    return false;
+  }
+  let detail = err.detail.as_deref().unwrap_or_default().to_lowercase();
+  detail.contains("notbefore") || detail.contains("notafter")
+}
+
+/// A preference for one of the certificate chains offered by the ACME
+/// server, used with [`Order::certificate_with_preference`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ChainPreference {
+  /// Prefer the chain that leads up to the root certificate with this
+  /// common name. This is the issuer of the last certificate in the chain.
+  RootCommonName(String),
+  /// Prefer the first chain that contains a certificate issued by an issuer
+  /// with this common name.
+  IssuerCommonName(String),
+  /// Prefer the chain with the fewest certificates.
+  Shortest,
+}
+
+fn issuer_common_name(cert: &X509) -> Option<String> {
+  cert
+    .issuer_name()
+    .entries_by_nid(Nid::COMMONNAME)
+    .next()
+    .and_then(|entry| entry.data().to_string().ok())
+}
+
+/// Select the index of the chain that matches the preference, if any.
+pub(crate) fn select_chain(
+  chains: &[Vec<X509>],
+  preference: &ChainPreference,
+) -> Option<usize> {
+  match preference {
+    ChainPreference::RootCommonName(name) => chains.iter().position(|chain| {
+      chain.last().and_then(issuer_common_name).as_ref() == Some(name)
+    }),
+    ChainPreference::IssuerCommonName(name) => {
+      chains.iter().position(|chain| {
+        chain
+          .iter()
+          .any(|cert| issuer_common_name(cert).as_ref() == Some(name))
+      })
+    }
+    ChainPreference::Shortest => chains
+      .iter()
+      .enumerate()
+      .min_by_key(|(_, chain)| chain.len())
+      .map(|(i, _)| i),
+  }
+}
+
 /// A certificate signing request.
 pub enum Csr {
   /// Automatic signing takes just a private key. The other details of
@@ -161,29 +428,37 @@ pub enum Csr {
   Custom(X509Req),
 }
 
-fn gen_csr(
+pub(crate) fn gen_csr(
   pkey: &PKey<openssl::pkey::Private>,
-  domains: Vec<String>,
+  identifiers: &[Identifier],
 ) -> Result<X509Req, Error> {
-  if domains.is_empty() {
+  if identifiers.is_empty() {
     return Err(Error::Validation(
-      "at least one domain name needs to be supplied",
+      "at least one identifier needs to be supplied",
     ));
   }
 
   let mut builder = X509Req::builder()?;
+  // IP addresses are not put in the common name, as they can only be
+  // represented as a SAN.
   let name = {
     let mut name = X509Name::builder()?;
-    name.append_entry_by_text("CN", &domains[0])?;
+    if let Some(dns) = identifiers.iter().find(|i| i.is_dns()) {
+      name.append_entry_by_text("CN", &dns.value)?;
+    }
     name.build()
   };
   builder.set_subject_name(&name)?;
 
-  // Add all domains as SANs
+  // Add all identifiers as SANs
   let san_extension = {
     let mut san = SubjectAlternativeName::new();
-    for domain in domains.iter() {
-      san.dns(domain);
+    for identifier in identifiers.iter() {
+      if identifier.is_ip() {
+        san.ip(&identifier.value);
+      } else {
+        san.dns(&identifier.value);
+      }
     }
     san.build(&builder.x509v3_context(None))?
   };
@@ -191,7 +466,7 @@ fn gen_csr(
   stack.push(san_extension)?;
   builder.add_extensions(&stack)?;
 
-  builder.set_pubkey(&pkey)?;
+  builder.set_pubkey(pkey)?;
   builder.sign(pkey, MessageDigest::sha256())?;
 
   Ok(builder.build())
@@ -211,14 +486,7 @@ impl Order {
   #[instrument(level = Level::INFO, name = "acme2::Order::finalize", err, skip(self, csr), fields(order_url = %self.url, status = field::Empty))]
   pub async fn finalize(&self, csr: Csr) -> Result<Order, Error> {
     let csr = match csr {
-      Csr::Automatic(pkey) => gen_csr(
-        &pkey,
-        self
-          .identifiers
-          .iter()
-          .map(|f| f.value.clone())
-          .collect::<Vec<_>>(),
-      )?,
+      Csr::Automatic(pkey) => gen_csr(&pkey, &self.identifiers)?,
       Csr::Custom(csr) => csr,
     };
 
@@ -231,13 +499,13 @@ impl Order {
       .authenticated_request::<_, Order>(
         &self.finalize_url,
         json!({ "csr": csr_b64 }),
-        account.private_key.clone().unwrap(),
+        &*account.signer(),
         Some(account.id.clone()),
       )
       .await?;
     let res: Result<Order, Error> = res.into();
     let mut order = res?;
-    Span::current().record("status", &field::debug(&order.status));
+    Span::current().record("status", field::debug(&order.status));
     order.account = Some(account.clone());
     order.url = self.url.clone();
     Ok(order)
@@ -245,28 +513,88 @@ impl Order {
 
   /// Download the certificate. The order must be in the [`OrderStatus::Valid`]
   /// state for this to complete.
+  ///
+  /// This downloads the default certificate chain. Use
+  /// [`Order::certificate_chains`] or [`Order::certificate_with_preference`]
+  /// to get one of the alternate chains offered by the ACME server.
   #[instrument(level = Level::INFO, name = "acme2::Order::certificate", err, skip(self), fields(order_url = %self.url, has_certificate = field::Empty))]
   pub async fn certificate(&self) -> Result<Option<Vec<X509>>, Error> {
-    Span::current().record("has_certificate", &self.certificate_url.is_some());
+    Span::current().record("has_certificate", self.certificate_url.is_some());
     let certificate_url = match self.certificate_url.clone() {
       Some(certificate_url) => certificate_url,
       None => return Ok(None),
     };
 
+    let (chain, _) = self.fetch_certificate(&certificate_url).await?;
+    Ok(Some(chain))
+  }
+
+  /// Download all certificate chains offered by the ACME server. The order
+  /// must be in the [`OrderStatus::Valid`] state for this to complete.
+  ///
+  /// The first chain is the default chain, followed by the alternate chains
+  /// (which usually lead to a different root certificate).
+  #[instrument(level = Level::INFO, name = "acme2::Order::certificate_chains", err, skip(self), fields(order_url = %self.url, has_certificate = field::Empty, alternates = field::Empty))]
+  pub async fn certificate_chains(
+    &self,
+  ) -> Result<Option<Vec<Vec<X509>>>, Error> {
+    Span::current().record("has_certificate", self.certificate_url.is_some());
+    let certificate_url = match self.certificate_url.clone() {
+      Some(certificate_url) => certificate_url,
+      None => return Ok(None),
+    };
+
+    let (chain, headers) = self.fetch_certificate(&certificate_url).await?;
+    let alternate_urls = extract_links(&headers, &certificate_url, "alternate");
+    Span::current().record("alternates", alternate_urls.len());
+
+    let mut chains = vec![chain];
+    for url in alternate_urls {
+      let (chain, _) = self.fetch_certificate(&url).await?;
+      chains.push(chain);
+    }
+
+    Ok(Some(chains))
+  }
+
+  /// Download the certificate chain that best matches the given preference.
+  /// The order must be in the [`OrderStatus::Valid`] state for this to
+  /// complete.
+  ///
+  /// If none of the chains offered by the ACME server match the preference,
+  /// the default chain is returned.
+  #[instrument(level = Level::INFO, name = "acme2::Order::certificate_with_preference", err, skip(self), fields(order_url = %self.url, has_certificate = field::Empty, alternates = field::Empty))]
+  pub async fn certificate_with_preference(
+    &self,
+    preference: ChainPreference,
+  ) -> Result<Option<Vec<X509>>, Error> {
+    Span::current().record("has_certificate", self.certificate_url.is_some());
+    let mut chains = match self.certificate_chains().await? {
+      Some(chains) => chains,
+      None => return Ok(None),
+    };
+    Span::current().record("alternates", chains.len() - 1);
+    let index = select_chain(&chains, &preference).unwrap_or(0);
+    Ok(Some(chains.swap_remove(index)))
+  }
+
+  async fn fetch_certificate(
+    &self,
+    url: &str,
+  ) -> Result<(Vec<X509>, reqwest::header::HeaderMap), Error> {
     let account = self.account.clone().unwrap();
     let directory = account.directory.clone().unwrap();
 
-    let bytes = directory
+    let (res, headers) = directory
       .authenticated_request_bytes(
-        &certificate_url,
+        url,
         "",
-        &account.private_key.clone().unwrap(),
+        &*account.signer(),
         &Some(account.id.clone()),
       )
-      .await?
-      .0?;
+      .await?;
 
-    Ok(Some(X509::stack_from_pem(&bytes)?))
+    Ok((X509::stack_from_pem(&res?)?, headers))
   }
 
   /// Update the order to match the current server state.
@@ -281,13 +609,13 @@ impl Order {
       .authenticated_request::<_, Order>(
         &self.url,
         json!(""),
-        account.private_key.clone().unwrap(),
+        &*account.signer(),
         Some(account.id.clone()),
       )
       .await?;
     let res: Result<Order, Error> = res.into();
     let mut order = res?;
-    Span::current().record("status", &field::debug(&order.status));
+    Span::current().record("status", field::debug(&order.status));
     order.account = Some(account.clone());
     order.url = self.url.clone();
     Ok(order)
@@ -0,0 +1,122 @@
+use crate::directory::Directory;
+use crate::error::*;
+use crate::helpers::*;
+use openssl::x509::X509Ref;
+use serde::Deserialize;
+use std::time::Duration;
+use std::time::SystemTime;
+use tracing::field;
+use tracing::instrument;
+use tracing::Level;
+use tracing::Span;
+
+/// The time window in which the ACME server suggests a certificate should
+/// be renewed.
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct SuggestedWindow {
+  /// The start of the window.
+  #[serde(deserialize_with = "deserialize_rfc3339")]
+  pub start: SystemTime,
+  /// The end of the window.
+  #[serde(deserialize_with = "deserialize_rfc3339")]
+  pub end: SystemTime,
+}
+
+impl SuggestedWindow {
+  /// Select a uniformly random point in time in this window, using the
+  /// system random. Clients should renew at this time, to spread the load
+  /// of many clients renewing at once.
+  pub fn random_time(&self) -> Result<SystemTime, Error> {
+    let length = match self.end.duration_since(self.start) {
+      Ok(length) if length.as_secs() > 0 => length.as_secs(),
+      _ => return Ok(self.start),
+    };
+
+    let mut buf = [0; 8];
+    openssl::rand::rand_bytes(&mut buf)?;
+    let offset = u64::from_be_bytes(buf) % (length + 1);
+
+    Ok(self.start + Duration::from_secs(offset))
+  }
+}
+
+/// The renewal information for a certificate (ACME Renewal Information,
+/// RFC 9773).
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RenewalInfo {
+  /// The window in which the certificate should be renewed.
+  pub suggested_window: SuggestedWindow,
+  /// A URL to a page explaining why the suggested window is what it is,
+  /// for example because of a mass revocation event.
+  #[serde(rename = "explanationURL")]
+  pub explanation_url: Option<String>,
+  /// How long to wait before fetching the renewal information again, as
+  /// indicated by the `Retry-After` header of the ACME server. If this is
+  /// `None`, the ACME server did not specify a delay, and clients should
+  /// check again in a few hours.
+  #[serde(skip)]
+  pub retry_after: Option<Duration>,
+}
+
+/// Compute the ARI certificate identifier of a certificate. This is the
+/// base64url encoded key identifier of the authority key identifier
+/// extension, and the base64url encoded DER serial number, joined by a `.`.
+///
+/// This identifier is used to fetch renewal information through
+/// [`Directory::renewal_info_by_id`], and to mark the certificate as being
+/// replaced through [`crate::OrderBuilder::replaces`].
+pub fn certificate_identifier(certificate: &X509Ref) -> Result<String, Error> {
+  let key_id = certificate.authority_key_id().ok_or(Error::Validation(
+    "certificate does not have an authority key identifier",
+  ))?;
+
+  // The serial number is encoded as the contents of a DER integer, so it
+  // must have a leading zero byte if the high bit is set.
+  let mut serial = certificate.serial_number().to_bn()?.to_vec();
+  if serial.is_empty() || serial[0] & 0x80 != 0 {
+    serial.insert(0, 0);
+  }
+
+  Ok(format!("{}.{}", b64(key_id.as_slice()), b64(&serial)))
+}
+
+impl Directory {
+  /// Fetch the renewal information for a certificate issued by this ACME
+  /// server.
+  pub async fn renewal_info(
+    &self,
+    certificate: &X509Ref,
+  ) -> Result<RenewalInfo, Error> {
+    self
+      .renewal_info_by_id(&certificate_identifier(certificate)?)
+      .await
+  }
+
+  /// Fetch the renewal information for a certificate, using its ARI
+  /// certificate identifier (see [`certificate_identifier`]).
+  #[instrument(level = Level::INFO, name = "acme2::Directory::renewal_info", err, skip(self), fields(retry_after = field::Empty))]
+  pub async fn renewal_info_by_id(
+    &self,
+    certificate_id: &str,
+  ) -> Result<RenewalInfo, Error> {
+    let renewal_info_url = self.renewal_info_url.clone().ok_or(
+      Error::Validation("the ACME server does not support renewal information"),
+    )?;
+
+    let url = format!(
+      "{}/{}",
+      renewal_info_url.trim_end_matches('/'),
+      certificate_id
+    );
+    let resp = self.http_client.get(&url).send().await?;
+    let retry_after = extract_retry_after(resp.headers());
+    Span::current().record("retry_after", field::debug(&retry_after));
+
+    let res: Result<RenewalInfo, Error> =
+      resp.json::<ServerResult<RenewalInfo>>().await?.into();
+    let mut info = res?;
+    info.retry_after = retry_after;
+    Ok(info)
+  }
+}
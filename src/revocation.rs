@@ -0,0 +1,112 @@
+use crate::account::Account;
+use crate::directory::Directory;
+use crate::error::*;
+use crate::helpers::*;
+use crate::jws::AccountSigner;
+use openssl::pkey::PKey;
+use openssl::pkey::Private;
+use openssl::x509::X509;
+use serde_json::json;
+use tracing::instrument;
+use tracing::Level;
+
+/// The reason a certificate is revoked, as defined in RFC 5280 section
+/// 5.3.1.
+///
+/// Not all ACME servers accept all reason codes.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RevocationReason {
+  Unspecified,
+  KeyCompromise,
+  CaCompromise,
+  AffiliationChanged,
+  Superseded,
+  CessationOfOperation,
+  CertificateHold,
+  RemoveFromCrl,
+  PrivilegeWithdrawn,
+  AaCompromise,
+}
+
+impl RevocationReason {
+  /// The numeric reason code of this reason.
+  pub fn code(&self) -> u8 {
+    match self {
+      RevocationReason::Unspecified => 0,
+      RevocationReason::KeyCompromise => 1,
+      RevocationReason::CaCompromise => 2,
+      RevocationReason::AffiliationChanged => 3,
+      RevocationReason::Superseded => 4,
+      RevocationReason::CessationOfOperation => 5,
+      RevocationReason::CertificateHold => 6,
+      RevocationReason::RemoveFromCrl => 8,
+      RevocationReason::PrivilegeWithdrawn => 9,
+      RevocationReason::AaCompromise => 10,
+    }
+  }
+}
+
+impl Directory {
+  async fn revoke_certificate_with_signer(
+    &self,
+    certificate: &X509,
+    reason: Option<RevocationReason>,
+    signer: &dyn AccountSigner,
+    account_id: Option<String>,
+  ) -> Result<(), Error> {
+    let mut payload = json!({ "certificate": b64(&certificate.to_der()?) });
+    if let Some(reason) = reason {
+      payload["reason"] = json!(reason.code());
+    }
+    let payload = serde_json::to_string(&payload)?;
+
+    self
+      .authenticated_request_bytes(
+        &self.revoke_cert_url,
+        &payload,
+        signer,
+        &account_id,
+      )
+      .await?
+      .0?;
+
+    Ok(())
+  }
+
+  /// Revoke a certificate using the private key of the certificate itself,
+  /// instead of an account key. This can be used if the account that
+  /// requested the certificate is not available, or when the certificate
+  /// private key has been compromised.
+  #[instrument(level = Level::INFO, name = "acme2::Directory::revoke_certificate_with_key", err, skip(self, certificate, private_key))]
+  pub async fn revoke_certificate_with_key(
+    &self,
+    certificate: &X509,
+    private_key: &PKey<Private>,
+    reason: Option<RevocationReason>,
+  ) -> Result<(), Error> {
+    self
+      .revoke_certificate_with_signer(certificate, reason, private_key, None)
+      .await
+  }
+}
+
+impl Account {
+  /// Revoke a certificate that was issued to this account (or for
+  /// identifiers this account is authorized for).
+  #[instrument(level = Level::INFO, name = "acme2::Account::revoke_certificate", err, skip(self, certificate), fields(account_id = %self.id))]
+  pub async fn revoke_certificate(
+    &self,
+    certificate: &X509,
+    reason: Option<RevocationReason>,
+  ) -> Result<(), Error> {
+    let directory = self.directory.clone().unwrap();
+    directory
+      .revoke_certificate_with_signer(
+        certificate,
+        reason,
+        &*self.signer(),
+        Some(self.id.clone()),
+      )
+      .await
+  }
+}
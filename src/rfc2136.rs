@@ -0,0 +1,444 @@
+use crate::dns::DnsProvider;
+use crate::dns_wire::*;
+use crate::error::*;
+use crate::helpers::normalize_name;
+use async_trait::async_trait;
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use tracing::debug;
+use tracing::field;
+use tracing::instrument;
+use tracing::Level;
+use tracing::Span;
+
+const OPCODE_UPDATE: u16 = 5;
+
+/// The allowed clock skew between us and the DNS server, in seconds.
+const TSIG_FUDGE: u16 = 300;
+
+/// The HMAC algorithm of a [`TsigKey`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TsigAlgorithm {
+  HmacSha256,
+  HmacSha384,
+  HmacSha512,
+}
+
+impl TsigAlgorithm {
+  /// The name of the algorithm, as used on the wire (RFC 8945 section 6).
+  pub fn name(&self) -> &'static str {
+    match self {
+      TsigAlgorithm::HmacSha256 => "hmac-sha256",
+      TsigAlgorithm::HmacSha384 => "hmac-sha384",
+      TsigAlgorithm::HmacSha512 => "hmac-sha512",
+    }
+  }
+
+  fn digest(&self) -> MessageDigest {
+    match self {
+      TsigAlgorithm::HmacSha256 => MessageDigest::sha256(),
+      TsigAlgorithm::HmacSha384 => MessageDigest::sha384(),
+      TsigAlgorithm::HmacSha512 => MessageDigest::sha512(),
+    }
+  }
+}
+
+/// A shared secret used to authenticate DNS UPDATE messages with TSIG (RFC
+/// 8945).
+#[derive(Clone)]
+pub struct TsigKey {
+  name: String,
+  algorithm: TsigAlgorithm,
+  secret: Vec<u8>,
+}
+
+impl TsigKey {
+  /// Create a key from its name (as configured on the DNS server), algorithm,
+  /// and raw secret.
+  pub fn new(name: String, algorithm: TsigAlgorithm, secret: Vec<u8>) -> Self {
+    TsigKey {
+      name,
+      algorithm,
+      secret,
+    }
+  }
+
+  /// Create a key from a base64 encoded secret, as found in BIND and Knot
+  /// configuration files.
+  pub fn from_base64(
+    name: String,
+    algorithm: TsigAlgorithm,
+    secret: &str,
+  ) -> Result<Self, Error> {
+    let secret = base64::decode(secret.trim())
+      .map_err(|_| Error::Validation("the TSIG secret is not valid base64"))?;
+    Ok(Self::new(name, algorithm, secret))
+  }
+
+  fn mac(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let key = PKey::hmac(&self.secret)?;
+    let mut signer = Signer::new(self.algorithm.digest(), &key)?;
+    signer.update(data)?;
+    Ok(signer.sign_to_vec()?)
+  }
+
+  /// The TSIG variables that are appended to the message when computing the
+  /// MAC (RFC 8945 section 4.3.3).
+  fn variables(
+    &self,
+    time_signed: u64,
+    fudge: u16,
+    error: u16,
+    other: &[u8],
+  ) -> Result<Vec<u8>, Error> {
+    let mut buf = vec![];
+    encode_name(&mut buf, &self.name)?;
+    buf.extend_from_slice(&CLASS_ANY.to_be_bytes());
+    buf.extend_from_slice(&0u32.to_be_bytes());
+    encode_name(&mut buf, self.algorithm.name())?;
+    buf.extend_from_slice(&time_signed.to_be_bytes()[2..]);
+    buf.extend_from_slice(&fudge.to_be_bytes());
+    buf.extend_from_slice(&error.to_be_bytes());
+    buf.extend_from_slice(&(other.len() as u16).to_be_bytes());
+    buf.extend_from_slice(other);
+    Ok(buf)
+  }
+}
+
+impl fmt::Debug for TsigKey {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("TsigKey")
+      .field("name", &self.name)
+      .field("algorithm", &self.algorithm)
+      .finish()
+  }
+}
+
+/// An error returned by the DNS server in response to an UPDATE message.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, thiserror::Error)]
+#[error("the DNS server rejected the update: {}", rcode_name(*.rcode))]
+pub struct DnsUpdateError {
+  /// The response code (RCODE) of the response, or the TSIG error if the
+  /// server could not authenticate the request.
+  pub rcode: u16,
+}
+
+fn rcode_name(rcode: u16) -> String {
+  let name = match rcode {
+    1 => "FORMERR",
+    2 => "SERVFAIL",
+    3 => "NXDOMAIN",
+    4 => "NOTIMP",
+    5 => "REFUSED",
+    6 => "YXDOMAIN",
+    7 => "YXRRSET",
+    8 => "NXRRSET",
+    9 => "NOTAUTH",
+    10 => "NOTZONE",
+    16 => "BADSIG",
+    17 => "BADKEY",
+    18 => "BADTIME",
+    22 => "BADTRUNC",
+    _ => return format!("RCODE {}", rcode),
+  };
+  name.to_string()
+}
+
+/// A builder that is used to create a [`Rfc2136Provider`].
+pub struct Rfc2136ProviderBuilder {
+  server: SocketAddr,
+  zone: String,
+  tsig_key: Option<TsigKey>,
+  ttl: u32,
+  timeout: Duration,
+}
+
+impl Rfc2136ProviderBuilder {
+  /// Creates a new builder for a provider that sends updates for `zone` to
+  /// the authoritative (primary) DNS server at `server`.
+  pub fn new(server: SocketAddr, zone: String) -> Self {
+    Rfc2136ProviderBuilder {
+      server,
+      zone,
+      tsig_key: None,
+      ttl: 60,
+      timeout: Duration::from_secs(10),
+    }
+  }
+
+  /// The TSIG key used to authenticate updates. Most servers refuse
+  /// unauthenticated updates.
+  pub fn tsig_key(&mut self, tsig_key: TsigKey) -> &mut Self {
+    self.tsig_key = Some(tsig_key);
+    self
+  }
+
+  /// The TTL of created TXT records, in seconds. Defaults to 60.
+  pub fn ttl(&mut self, ttl: u32) -> &mut Self {
+    self.ttl = ttl;
+    self
+  }
+
+  /// How long to wait for the DNS server to respond. Defaults to 10
+  /// seconds.
+  pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// Build a [`Rfc2136Provider`] using the given parameters.
+  pub fn build(&mut self) -> Result<Rfc2136Provider, Error> {
+    let zone = normalize_name(&self.zone);
+    encode_name(&mut vec![], &zone)?;
+    if let Some(key) = &self.tsig_key {
+      encode_name(&mut vec![], &key.name)?;
+    }
+
+    Ok(Rfc2136Provider {
+      server: self.server,
+      zone,
+      tsig_key: self.tsig_key.clone(),
+      ttl: self.ttl,
+      timeout: self.timeout,
+    })
+  }
+}
+
+/// A [`DnsProvider`] that manages TXT records using DNS UPDATE messages (RFC
+/// 2136), optionally authenticated with TSIG (RFC 8945).
+///
+/// This works with most authoritative DNS servers, like BIND, Knot, and
+/// PowerDNS. Must be created through a [`Rfc2136ProviderBuilder`].
+#[derive(Debug, Clone)]
+pub struct Rfc2136Provider {
+  server: SocketAddr,
+  zone: String,
+  tsig_key: Option<TsigKey>,
+  ttl: u32,
+  timeout: Duration,
+}
+
+/// A single record in the update section of an UPDATE message.
+struct UpdateRecord<'a> {
+  name: &'a str,
+  class: u16,
+  ttl: u32,
+  rdata: Vec<u8>,
+}
+
+impl Rfc2136Provider {
+  #[instrument(level = Level::DEBUG, name = "acme2::Rfc2136Provider::update", err, skip(self, record), fields(server = %self.server, zone = %self.zone, name = %record.name, id = field::Empty))]
+  async fn update(&self, record: UpdateRecord<'_>) -> Result<(), Error> {
+    let name = normalize_name(record.name);
+    if name != self.zone && !name.ends_with(&format!(".{}", self.zone)) {
+      return Err(Error::Validation(
+        "the record name is not part of the zone of the DNS provider",
+      ));
+    }
+
+    let id = random_id()?;
+    Span::current().record("id", id);
+
+    let mut msg = vec![];
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&(OPCODE_UPDATE << 11).to_be_bytes());
+    // ZOCOUNT, PRCOUNT, UPCOUNT, ADCOUNT
+    for count in [1u16, 0, 1, 0].iter() {
+      msg.extend_from_slice(&count.to_be_bytes());
+    }
+    encode_name(&mut msg, &self.zone)?;
+    msg.extend_from_slice(&TYPE_SOA.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+    encode_record(
+      &mut msg,
+      &name,
+      TYPE_TXT,
+      record.class,
+      record.ttl,
+      &record.rdata,
+    )?;
+
+    let request_mac = match &self.tsig_key {
+      Some(key) => Some(sign(&mut msg, key, unix_time())?),
+      None => None,
+    };
+
+    let response = exchange(self.server, &msg, self.timeout).await?;
+    verify_response(
+      &response,
+      id,
+      self.tsig_key.as_ref().zip(request_mac.as_deref()),
+    )?;
+    debug!("DNS UPDATE succeeded.");
+    Ok(())
+  }
+}
+
+#[async_trait]
+impl DnsProvider for Rfc2136Provider {
+  async fn set_txt_record(&self, name: &str, value: &str) -> Result<(), Error> {
+    self
+      .update(UpdateRecord {
+        name,
+        class: CLASS_IN,
+        ttl: self.ttl,
+        rdata: encode_txt(value),
+      })
+      .await
+  }
+
+  async fn remove_txt_record(
+    &self,
+    name: &str,
+    value: &str,
+  ) -> Result<(), Error> {
+    // Class NONE deletes the record with exactly this data (RFC 2136
+    // section 2.5.4).
+    self
+      .update(UpdateRecord {
+        name,
+        class: CLASS_NONE,
+        ttl: 0,
+        rdata: encode_txt(value),
+      })
+      .await
+  }
+}
+
+fn unix_time() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or(0)
+}
+
+/// Sign a message with a TSIG record (RFC 8945 section 5.1), returning the
+/// MAC of the request.
+pub(crate) fn sign(
+  msg: &mut Vec<u8>,
+  key: &TsigKey,
+  time_signed: u64,
+) -> Result<Vec<u8>, Error> {
+  let mut data = msg.clone();
+  data.extend_from_slice(&key.variables(time_signed, TSIG_FUDGE, 0, &[])?);
+  let mac = key.mac(&data)?;
+
+  let mut rdata = vec![];
+  encode_name(&mut rdata, key.algorithm.name())?;
+  rdata.extend_from_slice(&time_signed.to_be_bytes()[2..]);
+  rdata.extend_from_slice(&TSIG_FUDGE.to_be_bytes());
+  rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+  rdata.extend_from_slice(&mac);
+  rdata.extend_from_slice(&msg[..2]);
+  rdata.extend_from_slice(&0u16.to_be_bytes());
+  rdata.extend_from_slice(&0u16.to_be_bytes());
+
+  encode_record(msg, &key.name, TYPE_TSIG, CLASS_ANY, 0, &rdata)?;
+
+  let arcount = u16::from_be_bytes([msg[10], msg[11]]) + 1;
+  msg[10..12].copy_from_slice(&arcount.to_be_bytes());
+
+  Ok(mac)
+}
+
+/// Check the response code of a response, and verify its TSIG record if the
+/// request was signed (RFC 8945 section 5.3).
+pub(crate) fn verify_response(
+  response: &[u8],
+  id: u16,
+  tsig: Option<(&TsigKey, &[u8])>,
+) -> Result<(), Error> {
+  let mut reader = Reader::new(response);
+  if reader.u16()? != id {
+    return Err(transport_err("DNS response ID does not match the request"));
+  }
+  let flags = reader.u16()?;
+  if flags & FLAG_QR == 0 || (flags >> 11) & 0xf != OPCODE_UPDATE {
+    return Err(transport_err("DNS response is not an UPDATE response"));
+  }
+  let rcode = flags & 0xf;
+  let counts = [reader.u16()?, reader.u16()?, reader.u16()?, reader.u16()?];
+
+  for _ in 0..counts[0] {
+    reader.name()?;
+    reader.bytes(4)?;
+  }
+  // The counts come from the server, so their sum may not fit in a u16.
+  let records = counts[1] as usize + counts[2] as usize + counts[3] as usize;
+  let mut tsig_record = None;
+  for i in 0..records {
+    let start = reader.pos;
+    reader.name()?;
+    let r#type = reader.u16()?;
+    reader.bytes(6)?;
+    let rdlength = reader.u16()? as usize;
+    let rdata_start = reader.pos;
+    reader.bytes(rdlength)?;
+    if r#type == TYPE_TSIG && i == records - 1 && counts[3] > 0 {
+      tsig_record = Some((start, rdata_start));
+    }
+  }
+
+  let (key, request_mac) = match tsig {
+    Some(tsig) => tsig,
+    None if rcode != 0 => {
+      return Err(Error::Other(Box::new(DnsUpdateError { rcode })))
+    }
+    None => return Ok(()),
+  };
+
+  let (start, rdata_start) = match tsig_record {
+    Some(tsig_record) => tsig_record,
+    // Servers do not sign responses to requests they could not
+    // authenticate, so only require a signature on success.
+    None if rcode != 0 => {
+      return Err(Error::Other(Box::new(DnsUpdateError { rcode })))
+    }
+    None => return Err(transport_err("DNS response is not signed")),
+  };
+
+  let mut reader = Reader::new(response);
+  reader.pos = rdata_start;
+  reader.name()?;
+  let time_signed = reader.u48()?;
+  let fudge = reader.u16()?;
+  let mac_size = reader.u16()? as usize;
+  let mac = reader.bytes(mac_size)?;
+  let original_id = reader.bytes(2)?;
+  let error = reader.u16()?;
+  let other_len = reader.u16()? as usize;
+  let other = reader.bytes(other_len)?;
+
+  if error != 0 {
+    return Err(Error::Other(Box::new(DnsUpdateError { rcode: error })));
+  }
+
+  let mut data = (request_mac.len() as u16).to_be_bytes().to_vec();
+  data.extend_from_slice(request_mac);
+  let mut unsigned = response[..start].to_vec();
+  unsigned[..2].copy_from_slice(original_id);
+  unsigned[10..12].copy_from_slice(&(counts[3] - 1).to_be_bytes());
+  data.extend_from_slice(&unsigned);
+  data.extend_from_slice(&key.variables(time_signed, fudge, error, other)?);
+
+  if mac.len() != key.algorithm.digest().size()
+    || !memcmp::eq(mac, &key.mac(&data)?)
+  {
+    return Err(transport_err("DNS response has an invalid TSIG signature"));
+  }
+  if unix_time().abs_diff(time_signed) > fudge as u64 {
+    return Err(transport_err("DNS response TSIG time is out of range"));
+  }
+
+  if rcode != 0 {
+    return Err(Error::Other(Box::new(DnsUpdateError { rcode })));
+  }
+  Ok(())
+}
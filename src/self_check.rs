@@ -0,0 +1,191 @@
+use crate::authorization::Challenge;
+use crate::authorization::ACME_CHALLENGE_PATH_PREFIX;
+use crate::error::*;
+use reqwest::header::LOCATION;
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tracing::debug;
+use tracing::instrument;
+use tracing::Level;
+
+/// The maximum number of redirects that are followed, matching Let's
+/// Encrypt.
+const MAX_REDIRECTS: usize = 10;
+
+/// A pre-flight check that fetches the key authorization of a HTTP01
+/// challenge the way the ACME server will, before the server is asked to
+/// validate the challenge.
+///
+/// Failed validations invalidate the authorization and count against the
+/// failed validation limits of the CA, so it is cheaper to catch
+/// misconfigurations locally.
+///
+/// Like CAs do, redirects are followed (up to 10), but only to `http` and
+/// `https` URLs on the default ports, and certificates of `https` URLs are
+/// not verified.
+#[derive(Debug, Clone)]
+pub struct Http01SelfCheck {
+  resolve: HashMap<String, IpAddr>,
+  http_port: u16,
+  https_port: u16,
+  timeout: Duration,
+}
+
+impl Default for Http01SelfCheck {
+  fn default() -> Self {
+    Http01SelfCheck {
+      resolve: HashMap::new(),
+      http_port: 80,
+      https_port: 443,
+      timeout: Duration::from_secs(10),
+    }
+  }
+}
+
+impl Http01SelfCheck {
+  /// Create a self check with the default settings.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Connect to `ip` for requests to `host`, instead of resolving it
+  /// through DNS.
+  pub fn resolve(&mut self, host: String, ip: IpAddr) -> &mut Self {
+    self.resolve.insert(host, ip);
+    self
+  }
+
+  /// The port used for `http` URLs. Defaults to 80, which is the only port
+  /// ACME servers use for HTTP01 validation.
+  pub fn http_port(&mut self, http_port: u16) -> &mut Self {
+    self.http_port = http_port;
+    self
+  }
+
+  /// The port used for `https` URLs that are redirected to. Defaults to
+  /// 443.
+  pub fn https_port(&mut self, https_port: u16) -> &mut Self {
+    self.https_port = https_port;
+    self
+  }
+
+  /// The timeout for every request. Defaults to 10 seconds.
+  pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// Fetch the key authorization of the challenge, and compare it to the
+  /// expected value.
+  ///
+  /// Returns [`Error::Http01SelfCheckFailed`] if it could not be fetched or
+  /// does not match.
+  #[instrument(level = Level::INFO, name = "acme2::Http01SelfCheck::check", err, skip(self, challenge), fields(token = ?challenge.token))]
+  pub async fn check(&self, challenge: &Challenge) -> Result<(), Error> {
+    let identifier = challenge.identifier.as_ref().ok_or(Error::Validation(
+      "the challenge was not retrieved through an authorization",
+    ))?;
+    let token = challenge
+      .token
+      .as_deref()
+      .ok_or(Error::Validation("the challenge does not have a token"))?;
+    let key_authorization = challenge.key_authorization()?.unwrap();
+
+    let mut builder = reqwest::Client::builder()
+      .redirect(Policy::none())
+      .danger_accept_invalid_certs(true)
+      .timeout(self.timeout);
+    for (host, ip) in &self.resolve {
+      builder = builder.resolve(host, SocketAddr::new(*ip, 0));
+    }
+    let client = builder.build()?;
+
+    let mut url = Url::parse(&format!(
+      "http://{}:{}{}{}",
+      identifier.http_host(),
+      self.http_port,
+      ACME_CHALLENGE_PATH_PREFIX,
+      token
+    ))
+    .map_err(|err| failed(format!("invalid challenge URL: {}", err)))?;
+
+    for _ in 0..=MAX_REDIRECTS {
+      debug!({ url = %url }, "Fetching key authorization.");
+      let res = client
+        .get(url.clone())
+        .send()
+        .await
+        .map_err(|err| failed(format!("could not fetch {}: {}", url, err)))?;
+      let status = res.status();
+
+      if status.is_redirection() {
+        let location = res
+          .headers()
+          .get(LOCATION)
+          .and_then(|location| location.to_str().ok())
+          .ok_or_else(|| {
+            failed(format!("{} redirected without a location", url))
+          })?;
+        let next = url.join(location).map_err(|err| {
+          failed(format!("{} redirected to an invalid URL: {}", url, err))
+        })?;
+        url = self.redirect_target(next)?;
+        continue;
+      }
+
+      if !status.is_success() {
+        return Err(failed(format!("{} returned status {}", url, status)));
+      }
+      let body = res
+        .text()
+        .await
+        .map_err(|err| failed(format!("could not read {}: {}", url, err)))?;
+      if body.trim() != key_authorization {
+        return Err(failed(format!(
+          "{} did not return the key authorization",
+          url
+        )));
+      }
+      return Ok(());
+    }
+
+    Err(failed(format!(
+      "more than {} redirects were followed",
+      MAX_REDIRECTS
+    )))
+  }
+
+  /// Validate a redirect target, and map the default port of its scheme to
+  /// the configured one.
+  fn redirect_target(&self, mut url: Url) -> Result<Url, Error> {
+    let port = match url.scheme() {
+      "http" => self.http_port,
+      "https" => self.https_port,
+      _ => {
+        return Err(failed(format!(
+          "redirect to {} does not use http or https",
+          url
+        )))
+      }
+    };
+    match url.port() {
+      None => url.set_port(Some(port)).unwrap(),
+      Some(p) if p == port => {}
+      Some(_) => {
+        return Err(failed(format!(
+          "redirect to {} does not use the default port",
+          url
+        )))
+      }
+    }
+    Ok(url)
+  }
+}
+
+fn failed(reason: String) -> Error {
+  Error::Http01SelfCheckFailed(reason)
+}
@@ -0,0 +1,176 @@
+use crate::authorization::Authorization;
+use crate::authorization::AuthorizationStatus;
+use crate::authorization::Challenge;
+use crate::authorization::ChallengeStatus;
+use crate::challenge_policy::no_acceptable_challenge;
+use crate::challenge_policy::ChallengePolicy;
+use crate::error::*;
+use crate::order::Order;
+use async_trait::async_trait;
+use std::fmt::Debug;
+use std::time::Duration;
+use tracing::debug;
+use tracing::field;
+use tracing::instrument;
+use tracing::warn;
+use tracing::Level;
+use tracing::Span;
+
+/// A solver makes challenges satisfiable for the ACME server, and tears
+/// them down again once validation has finished.
+///
+/// Solvers are used with [`Order::solve_challenges`], which takes care of
+/// picking a challenge, validating it, and calling
+/// [`ChallengeSolver::cleanup`].
+#[async_trait]
+pub trait ChallengeSolver: Debug + Send + Sync {
+  /// The challenge types this solver can fulfill, in order of preference.
+  ///
+  /// Example: `&["http-01"]`
+  fn supported_types(&self) -> &[&str];
+
+  /// Make the challenge satisfiable, for example by serving the key
+  /// authorization over HTTP, or by creating a DNS record.
+  async fn present(
+    &self,
+    authorization: &Authorization,
+    challenge: &Challenge,
+  ) -> Result<(), Error>;
+
+  /// Tear down everything that was set up in [`ChallengeSolver::present`].
+  ///
+  /// This is called after validation has finished, even if presenting or
+  /// validating the challenge failed.
+  async fn cleanup(
+    &self,
+    authorization: &Authorization,
+    challenge: &Challenge,
+  ) -> Result<(), Error>;
+}
+
+/// Pick the challenge of the authorization that the solver prefers.
+pub(crate) fn select_challenge(
+  authorization: &Authorization,
+  solver: &dyn ChallengeSolver,
+) -> Option<Challenge> {
+  solver
+    .supported_types()
+    .iter()
+    .find_map(|r#type| authorization.get_challenge(r#type))
+}
+
+impl Order {
+  /// Complete all pending [`Authorization`]s of this order using `solver`.
+  ///
+  /// For every pending authorization the challenge preferred by the solver
+  /// is presented, validated, and waited on. The challenge is cleaned up
+  /// afterwards, also if presenting or validating it failed. Authorizations
+  /// are completed one after the other.
+  ///
+  /// `poll_interval` and `attempts` are used to wait for each challenge, see
+  /// [`Challenge::wait_done`]. After this completes, use
+  /// [`Order::wait_ready`] to wait for the order to become ready.
+  ///
+  /// Returns [`Error::NoAcceptableChallenge`] if the ACME server did not
+  /// offer a challenge the solver supports.
+  #[instrument(level = Level::INFO, name = "acme2::Order::solve_challenges", err, skip(self, solver), fields(order = %self.url))]
+  pub async fn solve_challenges(
+    &self,
+    solver: &dyn ChallengeSolver,
+    poll_interval: Duration,
+    attempts: usize,
+  ) -> Result<(), Error> {
+    self
+      .solve_pending(poll_interval, attempts, solver, |authorization| {
+        select_challenge(authorization, solver)
+          .ok_or_else(|| no_acceptable_challenge(authorization))
+      })
+      .await
+  }
+
+  /// Like [`Order::solve_challenges`], but `policy` decides which challenge
+  /// type is used for each authorization. Challenge types that the solver
+  /// does not support are skipped.
+  #[instrument(level = Level::INFO, name = "acme2::Order::solve_challenges_with_policy", err, skip(self, solver, policy), fields(order = %self.url))]
+  pub async fn solve_challenges_with_policy(
+    &self,
+    solver: &dyn ChallengeSolver,
+    policy: &ChallengePolicy,
+    poll_interval: Duration,
+    attempts: usize,
+  ) -> Result<(), Error> {
+    self
+      .solve_pending(poll_interval, attempts, solver, |authorization| {
+        policy.select_supported(authorization, Some(solver.supported_types()))
+      })
+      .await
+  }
+
+  async fn solve_pending(
+    &self,
+    poll_interval: Duration,
+    attempts: usize,
+    solver: &dyn ChallengeSolver,
+    select: impl Fn(&Authorization) -> Result<Challenge, Error>,
+  ) -> Result<(), Error> {
+    for authorization in self.authorizations().await? {
+      if authorization.status != AuthorizationStatus::Pending {
+        debug!(
+          { identifier = ?authorization.identifier, status = ?authorization.status },
+          "Skipping authorization that is not pending."
+        );
+        continue;
+      }
+
+      let challenge = select(&authorization)?;
+
+      solve_challenge(
+        &authorization,
+        challenge,
+        solver,
+        poll_interval,
+        attempts,
+      )
+      .await?;
+    }
+
+    Ok(())
+  }
+}
+
+#[instrument(level = Level::INFO, name = "acme2::solve_challenge", err, skip(authorization, challenge, solver), fields(identifier = ?authorization.identifier, r#type = %challenge.r#type, status = field::Empty))]
+pub(crate) async fn solve_challenge(
+  authorization: &Authorization,
+  challenge: Challenge,
+  solver: &dyn ChallengeSolver,
+  poll_interval: Duration,
+  attempts: usize,
+) -> Result<(), Error> {
+  let result = async {
+    solver.present(authorization, &challenge).await?;
+    let challenge = challenge.validate().await?;
+    let challenge = challenge.wait_done(poll_interval, attempts).await?;
+    Span::current().record("status", field::debug(&challenge.status));
+    match (challenge.status, challenge.error) {
+      (ChallengeStatus::Valid, _) => Ok(()),
+      (ChallengeStatus::Invalid, Some(err)) => Err(err.into()),
+      (ChallengeStatus::Invalid, None) => Err(Error::Validation(
+        "the ACME server could not validate the challenge",
+      )),
+      (_, _) => Err(Error::Validation("the challenge did not become valid")),
+    }
+  }
+  .await;
+
+  let cleanup = solver.cleanup(authorization, &challenge).await;
+
+  match (result, cleanup) {
+    (Err(err), Err(cleanup_err)) => {
+      // The original error is more useful to the caller than a failure to
+      // clean up afterwards, so only log the latter.
+      warn!({ error = %cleanup_err }, "Failed to clean up challenge.");
+      Err(err)
+    }
+    (result, cleanup) => result.and(cleanup),
+  }
+}
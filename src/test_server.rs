@@ -0,0 +1,1859 @@
+use crate::error::*;
+use crate::helpers::*;
+use crate::jws::Jwk;
+use crate::jws::JwsAlgorithm;
+use crate::renewal::certificate_identifier;
+use async_trait::async_trait;
+use hyper::header;
+use hyper::service::make_service_fn;
+use hyper::service::service_fn;
+use hyper::Body;
+use hyper::Method;
+use hyper::Request;
+use hyper::Response;
+use hyper::Server;
+use hyper::StatusCode;
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::bn::MsbOption;
+use openssl::ec::EcGroup;
+use openssl::ec::EcKey;
+use openssl::ecdsa::EcdsaSig;
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::Id;
+use openssl::pkey::PKey;
+use openssl::pkey::PKeyRef;
+use openssl::pkey::Private;
+use openssl::pkey::Public;
+use openssl::rsa::Rsa;
+use openssl::sign::Verifier;
+use openssl::x509::extension::AuthorityKeyIdentifier;
+use openssl::x509::extension::BasicConstraints;
+use openssl::x509::extension::ExtendedKeyUsage;
+use openssl::x509::extension::KeyUsage;
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::extension::SubjectKeyIdentifier;
+use openssl::x509::X509Name;
+use openssl::x509::X509Req;
+use openssl::x509::X509;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::convert::TryFrom;
+use std::fmt::Debug;
+use std::net::IpAddr;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::sync::Mutex;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::task::JoinHandle;
+use tracing::debug;
+use tracing::instrument;
+use tracing::Level;
+
+const PROBLEM_PREFIX: &str = "urn:ietf:params:acme:error:";
+
+/// The lifetime of certificates issued by the test server, unless the order
+/// requests a different validity. Orders may not request a longer validity.
+const CERTIFICATE_LIFETIME: time::Duration = time::Duration::days(90);
+
+/// A challenge that the [`TestServer`] was asked to validate.
+#[derive(Debug, Clone)]
+pub struct ValidationRequest {
+  /// The identifier of the authorization the challenge belongs to. For
+  /// wildcard authorizations this is the domain without the `*.` prefix.
+  pub identifier: Identifier,
+  /// The type of the challenge, for example `http-01`.
+  pub r#type: String,
+  /// The token of the challenge.
+  pub token: String,
+  /// The key authorization the client is expected to provision.
+  pub key_authorization: String,
+}
+
+/// Decides whether the [`TestServer`] considers a challenge valid.
+///
+/// Without a validator, the test server accepts every challenge that the
+/// client asks it to validate.
+#[async_trait]
+pub trait ChallengeValidator: Debug + Send + Sync {
+  /// Validate the challenge. Returning an error marks the challenge (and
+  /// its authorization) as invalid, with the error as the problem detail.
+  async fn validate(&self, request: &ValidationRequest) -> Result<(), String>;
+}
+
+/// A builder used to start a [`TestServer`].
+#[derive(Debug, Default)]
+pub struct TestServerBuilder {
+  validator: Option<Arc<dyn ChallengeValidator>>,
+  profiles: HashMap<String, String>,
+  orders_page_size: Option<usize>,
+}
+
+impl TestServerBuilder {
+  /// Create a new builder with the default settings.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Validate challenges with a custom validator, instead of accepting
+  /// every challenge.
+  pub fn validator(
+    &mut self,
+    validator: Arc<dyn ChallengeValidator>,
+  ) -> &mut Self {
+    self.validator = Some(validator);
+    self
+  }
+
+  /// Offer a certificate profile with the given name and description in the
+  /// directory metadata.
+  pub fn profile(&mut self, name: String, description: String) -> &mut Self {
+    self.profiles.insert(name, description);
+    self
+  }
+
+  /// Split the orders list of accounts into pages of at most this many
+  /// orders, linked with `Link: rel="next"` headers. By default all orders
+  /// are returned in a single page.
+  pub fn orders_page_size(&mut self, page_size: usize) -> &mut Self {
+    self.orders_page_size = Some(page_size.max(1));
+    self
+  }
+
+  /// Start the server on a random port on the loopback interface.
+  ///
+  /// This must be called from within a Tokio runtime, which the server is
+  /// spawned on. The server is stopped when the [`TestServer`] is dropped.
+  #[instrument(level = Level::INFO, name = "acme2::TestServerBuilder::build", err, skip(self))]
+  pub async fn build(&mut self) -> Result<TestServer, Error> {
+    let listener = map_io_err(TcpListener::bind(("127.0.0.1", 0)))?;
+    map_io_err(listener.set_nonblocking(true))?;
+    let base_url = format!("http://{}", map_io_err(listener.local_addr())?);
+
+    let ca = Ca::new()?;
+    let root = ca.root.clone();
+    let alternate_root = ca.alternate_root.clone();
+    let handler = Arc::new(Handler {
+      base_url: base_url.clone(),
+      validator: self.validator.clone(),
+      profiles: self.profiles.clone(),
+      orders_page_size: self.orders_page_size,
+      ca,
+      state: Mutex::new(State::default()),
+    });
+
+    let make_service = make_service_fn(move |_| {
+      let handler = handler.clone();
+      async move {
+        Ok::<_, Infallible>(service_fn(move |req| handler.clone().handle(req)))
+      }
+    });
+    let server = Server::from_tcp(listener)
+      .map_err(|err| Error::Other(Box::new(err)))?
+      .serve(make_service);
+    let task = tokio::spawn(async move {
+      if let Err(err) = server.await {
+        debug!({ error = %err }, "Test server failed.");
+      }
+    });
+    debug!({ url = %base_url }, "Started test server.");
+
+    Ok(TestServer {
+      directory_url: format!("{}/directory", base_url),
+      root,
+      alternate_root,
+      task,
+    })
+  }
+}
+
+/// An in-process ACME server (RFC 8555) for tests, that issues certificates
+/// from a locally generated CA.
+///
+/// It supports accounts (including key rollover and deactivation), orders,
+/// authorizations, challenges, finalization, certificate download (including
+/// an alternate chain), revocation and renewal information. Requests are verified like a real
+/// ACME server would (signatures, nonces and URLs), but challenges are
+/// either accepted as is, or checked by a [`ChallengeValidator`].
+///
+/// The server only serves plain HTTP on the loopback interface, and is not
+/// meant to be used outside of tests.
+#[derive(Debug)]
+pub struct TestServer {
+  directory_url: String,
+  root: X509,
+  alternate_root: X509,
+  task: JoinHandle<()>,
+}
+
+impl TestServer {
+  /// Start a server that accepts every challenge.
+  pub async fn start() -> Result<TestServer, Error> {
+    TestServerBuilder::new().build().await
+  }
+
+  /// The URL of the directory of this server, to be used with
+  /// [`crate::DirectoryBuilder`].
+  pub fn directory_url(&self) -> &str {
+    &self.directory_url
+  }
+
+  /// The root certificate of the CA that issues the certificates.
+  pub fn root_certificate(&self) -> &X509 {
+    &self.root
+  }
+
+  /// The root certificate of the alternate certificate chain, which is
+  /// offered next to the default chain through a `Link: rel="alternate"`
+  /// header.
+  pub fn alternate_root_certificate(&self) -> &X509 {
+    &self.alternate_root
+  }
+}
+
+impl Drop for TestServer {
+  fn drop(&mut self) {
+    self.task.abort();
+  }
+}
+
+/// The local CA of the test server. Certificates are issued by an
+/// intermediate, which is included in the certificate chain. The
+/// intermediate is cross-signed by an alternate root, for the alternate
+/// chain.
+struct Ca {
+  root: X509,
+  alternate_root: X509,
+  intermediate: X509,
+  alternate_intermediate: X509,
+  intermediate_key: PKey<Private>,
+}
+
+impl Ca {
+  fn new() -> Result<Ca, ErrorStack> {
+    let mut suffix = [0; 4];
+    openssl::rand::rand_bytes(&mut suffix)?;
+    let suffix: String = suffix.iter().map(|b| format!("{:02x}", b)).collect();
+
+    let root_key = gen_ca_key()?;
+    let root_name = ca_name(&format!("acme2 test root {}", suffix))?;
+    let root = ca_certificate(&root_name, &root_key, None, None)?;
+
+    let alternate_root_key = gen_ca_key()?;
+    let alternate_root_name =
+      ca_name(&format!("acme2 test alternate root {}", suffix))?;
+    let alternate_root =
+      ca_certificate(&alternate_root_name, &alternate_root_key, None, None)?;
+
+    let intermediate_key = gen_ca_key()?;
+    let intermediate_name =
+      ca_name(&format!("acme2 test intermediate {}", suffix))?;
+    let intermediate = ca_certificate(
+      &intermediate_name,
+      &intermediate_key,
+      Some((&root, &root_key)),
+      Some(0),
+    )?;
+    let alternate_intermediate = ca_certificate(
+      &intermediate_name,
+      &intermediate_key,
+      Some((&alternate_root, &alternate_root_key)),
+      Some(0),
+    )?;
+
+    Ok(Ca {
+      root,
+      alternate_root,
+      intermediate,
+      alternate_intermediate,
+      intermediate_key,
+    })
+  }
+
+  /// The PEM encoded chain of a certificate issued by this CA, leading to
+  /// either the root or the alternate root.
+  fn chain_pem(
+    &self,
+    certificate: &X509,
+    alternate: bool,
+  ) -> Result<Vec<u8>, ErrorStack> {
+    let intermediate = if alternate {
+      &self.alternate_intermediate
+    } else {
+      &self.intermediate
+    };
+    let mut pem = certificate.to_pem()?;
+    pem.extend(intermediate.to_pem()?);
+    Ok(pem)
+  }
+
+  /// Issue a certificate for the public key of the CSR, with the
+  /// identifiers as SANs.
+  fn issue(
+    &self,
+    csr: &X509Req,
+    identifiers: &[Identifier],
+    not_before: OffsetDateTime,
+    not_after: OffsetDateTime,
+  ) -> Result<X509, ErrorStack> {
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    let serial = random_serial()?.to_asn1_integer()?;
+    builder.set_serial_number(&serial)?;
+    let mut name = X509Name::builder()?;
+    if let Some(dns) = identifiers.iter().find(|i| i.is_dns()) {
+      if dns.value.len() <= 64 {
+        name.append_entry_by_text("CN", &dns.value)?;
+      }
+    }
+    builder.set_subject_name(&name.build())?;
+    builder.set_issuer_name(self.intermediate.subject_name())?;
+    builder.set_pubkey(&*csr.public_key()?)?;
+    builder
+      .set_not_before(&*Asn1Time::from_unix(not_before.unix_timestamp())?)?;
+    builder
+      .set_not_after(&*Asn1Time::from_unix(not_after.unix_timestamp())?)?;
+
+    builder.append_extension(BasicConstraints::new().critical().build()?)?;
+    builder.append_extension(
+      KeyUsage::new().critical().digital_signature().build()?,
+    )?;
+    builder.append_extension(ExtendedKeyUsage::new().server_auth().build()?)?;
+    let subject_key_id = SubjectKeyIdentifier::new()
+      .build(&builder.x509v3_context(Some(&self.intermediate), None))?;
+    builder.append_extension(subject_key_id)?;
+    let authority_key_id = AuthorityKeyIdentifier::new()
+      .keyid(true)
+      .build(&builder.x509v3_context(Some(&self.intermediate), None))?;
+    builder.append_extension(authority_key_id)?;
+    let mut san = SubjectAlternativeName::new();
+    for identifier in identifiers {
+      if identifier.is_ip() {
+        san.ip(&identifier.value);
+      } else {
+        san.dns(&identifier.value);
+      }
+    }
+    let san =
+      san.build(&builder.x509v3_context(Some(&self.intermediate), None))?;
+    builder.append_extension(san)?;
+
+    builder.sign(&self.intermediate_key, MessageDigest::sha256())?;
+    Ok(builder.build())
+  }
+}
+
+fn gen_ca_key() -> Result<PKey<Private>, ErrorStack> {
+  let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+  PKey::from_ec_key(EcKey::generate(&group)?)
+}
+
+fn ca_name(common_name: &str) -> Result<X509Name, ErrorStack> {
+  let mut name = X509Name::builder()?;
+  name.append_entry_by_text("O", "acme2")?;
+  name.append_entry_by_text("CN", common_name)?;
+  Ok(name.build())
+}
+
+fn random_serial() -> Result<BigNum, ErrorStack> {
+  let mut serial = BigNum::new()?;
+  serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+  Ok(serial)
+}
+
+/// Create a CA certificate, that is self signed if no issuer is given.
+fn ca_certificate(
+  name: &X509Name,
+  key: &PKey<Private>,
+  issuer: Option<(&X509, &PKey<Private>)>,
+  path_length: Option<u32>,
+) -> Result<X509, ErrorStack> {
+  let mut builder = X509::builder()?;
+  builder.set_version(2)?;
+  let serial = random_serial()?.to_asn1_integer()?;
+  builder.set_serial_number(&serial)?;
+  builder.set_subject_name(name)?;
+  builder
+    .set_issuer_name(issuer.map_or(name, |(cert, _)| cert.subject_name()))?;
+  builder.set_pubkey(key)?;
+  builder.set_not_before(&*Asn1Time::days_from_now(0)?)?;
+  builder.set_not_after(&*Asn1Time::days_from_now(3650)?)?;
+
+  let mut basic_constraints = BasicConstraints::new();
+  basic_constraints.critical().ca();
+  if let Some(path_length) = path_length {
+    basic_constraints.pathlen(path_length);
+  }
+  builder.append_extension(basic_constraints.build()?)?;
+  builder.append_extension(
+    KeyUsage::new()
+      .critical()
+      .key_cert_sign()
+      .crl_sign()
+      .digital_signature()
+      .build()?,
+  )?;
+  let issuer_cert = issuer.map(|(cert, _)| &**cert);
+  let subject_key_id = SubjectKeyIdentifier::new()
+    .build(&builder.x509v3_context(issuer_cert, None))?;
+  builder.append_extension(subject_key_id)?;
+  if issuer_cert.is_some() {
+    let authority_key_id = AuthorityKeyIdentifier::new()
+      .keyid(true)
+      .build(&builder.x509v3_context(issuer_cert, None))?;
+    builder.append_extension(authority_key_id)?;
+  }
+
+  builder.sign(issuer.map_or(key, |(_, key)| key), MessageDigest::sha256())?;
+  Ok(builder.build())
+}
+
+/// An ACME problem document, returned for failed requests.
+#[derive(Debug)]
+struct Problem {
+  status: StatusCode,
+  r#type: &'static str,
+  detail: String,
+}
+
+impl Problem {
+  fn new(status: StatusCode, r#type: &'static str, detail: &str) -> Self {
+    Problem {
+      status,
+      r#type,
+      detail: detail.to_string(),
+    }
+  }
+
+  fn malformed(detail: &str) -> Self {
+    Self::new(StatusCode::BAD_REQUEST, "malformed", detail)
+  }
+
+  fn unauthorized(detail: &str) -> Self {
+    Self::new(StatusCode::FORBIDDEN, "unauthorized", detail)
+  }
+
+  fn not_found() -> Self {
+    Self::new(
+      StatusCode::NOT_FOUND,
+      "malformed",
+      "resource does not exist",
+    )
+  }
+
+  fn into_response(self) -> Response<Body> {
+    let body = json!({
+      "type": format!("{}{}", PROBLEM_PREFIX, self.r#type),
+      "detail": self.detail,
+      "status": self.status.as_u16(),
+    });
+    let mut res = json_response(self.status, &body);
+    res.headers_mut().insert(
+      header::CONTENT_TYPE,
+      header::HeaderValue::from_static("application/problem+json"),
+    );
+    res
+  }
+}
+
+impl From<ErrorStack> for Problem {
+  fn from(err: ErrorStack) -> Self {
+    Self::new(
+      StatusCode::INTERNAL_SERVER_ERROR,
+      "serverInternal",
+      &err.to_string(),
+    )
+  }
+}
+
+impl From<Error> for Problem {
+  fn from(err: Error) -> Self {
+    Self::new(
+      StatusCode::INTERNAL_SERVER_ERROR,
+      "serverInternal",
+      &err.to_string(),
+    )
+  }
+}
+
+fn json_response(status: StatusCode, body: &Value) -> Response<Body> {
+  Response::builder()
+    .status(status)
+    .header(header::CONTENT_TYPE, "application/json")
+    .body(Body::from(body.to_string()))
+    .unwrap()
+}
+
+fn with_location(mut res: Response<Body>, url: &str) -> Response<Body> {
+  res.headers_mut().insert(
+    header::LOCATION,
+    header::HeaderValue::from_str(url).unwrap(),
+  );
+  res
+}
+
+fn map_io_err<T>(res: std::io::Result<T>) -> Result<T, Error> {
+  res.map_err(|err| Error::Other(Box::new(err)))
+}
+
+fn random_b64(len: usize) -> Result<String, ErrorStack> {
+  let mut buf = vec![0; len];
+  openssl::rand::rand_bytes(&mut buf)?;
+  Ok(b64(&buf))
+}
+
+fn now() -> OffsetDateTime {
+  OffsetDateTime::now_utc()
+}
+
+fn format_time(time: OffsetDateTime) -> String {
+  time.format(&Rfc3339).unwrap()
+}
+
+#[derive(Debug, Default)]
+struct State {
+  next_id: u64,
+  nonces: HashSet<String>,
+  accounts: HashMap<String, AccountState>,
+  orders: HashMap<String, OrderState>,
+  authorizations: HashMap<String, AuthorizationState>,
+  challenges: HashMap<String, String>,
+  certificates: HashMap<String, CertificateState>,
+}
+
+impl State {
+  fn next_id(&mut self) -> String {
+    self.next_id += 1;
+    self.next_id.to_string()
+  }
+}
+
+#[derive(Debug)]
+struct AccountState {
+  jwk: Jwk,
+  status: &'static str,
+  contact: Vec<String>,
+  terms_of_service_agreed: bool,
+  orders: Vec<String>,
+}
+
+#[derive(Debug)]
+struct OrderState {
+  account: String,
+  status: &'static str,
+  expires: OffsetDateTime,
+  identifiers: Vec<Identifier>,
+  not_before: Option<OffsetDateTime>,
+  not_after: Option<OffsetDateTime>,
+  profile: Option<String>,
+  replaces: Option<String>,
+  authorizations: Vec<String>,
+  certificate: Option<String>,
+}
+
+#[derive(Debug)]
+struct AuthorizationState {
+  account: String,
+  status: &'static str,
+  expires: OffsetDateTime,
+  identifier: Identifier,
+  wildcard: bool,
+  challenges: Vec<ChallengeState>,
+}
+
+#[derive(Debug)]
+struct ChallengeState {
+  id: String,
+  r#type: &'static str,
+  status: &'static str,
+  token: String,
+  validated: Option<OffsetDateTime>,
+  error: Option<Value>,
+}
+
+#[derive(Debug)]
+struct CertificateState {
+  account: String,
+  certificate: X509,
+  revoked: bool,
+}
+
+#[derive(Deserialize)]
+struct FlattenedJws {
+  protected: String,
+  payload: String,
+  signature: String,
+}
+
+#[derive(Deserialize)]
+struct ProtectedHeader {
+  alg: String,
+  nonce: Option<String>,
+  url: String,
+  kid: Option<String>,
+  jwk: Option<Jwk>,
+}
+
+/// A verified JWS request.
+struct SignedRequest {
+  /// The account that signed the request, if it was signed with a `kid`.
+  account: Option<String>,
+  /// The key that signed the request.
+  jwk: Jwk,
+  payload: Vec<u8>,
+}
+
+impl SignedRequest {
+  /// The payload, parsed as JSON. POST-as-GET requests have an empty
+  /// payload, which is returned as `None`.
+  fn json<T: serde::de::DeserializeOwned>(&self) -> Result<Option<T>, Problem> {
+    if self.payload.is_empty() {
+      return Ok(None);
+    }
+    serde_json::from_slice(&self.payload)
+      .map(Some)
+      .map_err(|_| Problem::malformed("the request payload is malformed"))
+  }
+
+  /// The account that signed the request. Fails if it was signed with a
+  /// `jwk` instead.
+  fn account(&self) -> Result<&str, Problem> {
+    self.account.as_deref().ok_or_else(|| {
+      Problem::malformed("the request must be signed with a kid")
+    })
+  }
+}
+
+/// A decoded flattened JWS, whose signature has not been verified yet.
+struct ParsedJws {
+  header: ProtectedHeader,
+  signing_input: String,
+  payload: Vec<u8>,
+  signature: Vec<u8>,
+}
+
+/// Decode a flattened JWS.
+fn parse_jws(body: &[u8]) -> Result<ParsedJws, Problem> {
+  let jws: FlattenedJws = serde_json::from_slice(body)
+    .map_err(|_| Problem::malformed("the request is not a flattened JWS"))?;
+  let header: ProtectedHeader = b64_decode(&jws.protected)
+    .ok()
+    .and_then(|header| serde_json::from_slice(&header).ok())
+    .ok_or_else(|| {
+      Problem::malformed("the JWS protected header is malformed")
+    })?;
+  let payload = b64_decode(&jws.payload)
+    .map_err(|_| Problem::malformed("the JWS payload is malformed"))?;
+  let signature = b64_decode(&jws.signature)
+    .map_err(|_| Problem::malformed("the JWS signature is malformed"))?;
+  Ok(ParsedJws {
+    header,
+    signing_input: format!("{}.{}", jws.protected, jws.payload),
+    payload,
+    signature,
+  })
+}
+
+impl ParsedJws {
+  /// Verify the signature with the key `jwk`.
+  fn verify(&self, jwk: &Jwk) -> Result<(), Problem> {
+    let key = jwk_public_key(jwk).map_err(|_| {
+      Problem::new(
+        StatusCode::BAD_REQUEST,
+        "badPublicKey",
+        "the JWS key is not supported",
+      )
+    })?;
+    let alg = match JwsAlgorithm::from_key(&key) {
+      Ok(alg) if alg.as_str() == self.header.alg => alg,
+      _ => {
+        return Err(Problem::new(
+          StatusCode::BAD_REQUEST,
+          "badSignatureAlgorithm",
+          "the JWS algorithm is not supported for this key",
+        ))
+      }
+    };
+    let valid = verify_signature(
+      alg,
+      &key,
+      self.signing_input.as_bytes(),
+      &self.signature,
+    )
+    .unwrap_or(false);
+    if !valid {
+      return Err(Problem::malformed("the JWS signature is invalid"));
+    }
+    Ok(())
+  }
+}
+
+fn b64_decode(data: &str) -> Result<Vec<u8>, base64::DecodeError> {
+  base64::decode_config(data, ::base64::URL_SAFE_NO_PAD)
+}
+
+/// The public key a JWK describes.
+fn jwk_public_key(jwk: &Jwk) -> Result<PKey<Public>, Error> {
+  let field = |value: &Option<String>| {
+    value
+      .as_deref()
+      .and_then(|value| b64_decode(value).ok())
+      .ok_or(Error::Validation("malformed JWK"))
+  };
+  match (jwk.kty.as_str(), jwk.crv.as_deref()) {
+    ("RSA", _) => {
+      let n = BigNum::from_slice(&field(&jwk.n)?)?;
+      let e = BigNum::from_slice(&field(&jwk.e)?)?;
+      Ok(PKey::from_rsa(Rsa::from_public_components(n, e)?)?)
+    }
+    ("EC", Some(crv)) => {
+      let nid = match crv {
+        "P-256" => Nid::X9_62_PRIME256V1,
+        "P-384" => Nid::SECP384R1,
+        _ => return Err(Error::Validation("unsupported EC curve")),
+      };
+      let group = EcGroup::from_curve_name(nid)?;
+      let x = BigNum::from_slice(&field(&jwk.x)?)?;
+      let y = BigNum::from_slice(&field(&jwk.y)?)?;
+      let ec = EcKey::from_public_key_affine_coordinates(&group, &x, &y)?;
+      Ok(PKey::from_ec_key(ec)?)
+    }
+    ("OKP", Some("Ed25519")) => Ok(PKey::public_key_from_raw_bytes(
+      &field(&jwk.x)?,
+      Id::ED25519,
+    )?),
+    _ => Err(Error::Validation("unsupported account key type")),
+  }
+}
+
+/// Verify a JWS signature over `data`, made with the account key `key`
+/// using the algorithm `alg`, which must match the key.
+fn verify_signature(
+  alg: JwsAlgorithm,
+  key: &PKeyRef<Public>,
+  data: &[u8],
+  signature: &[u8],
+) -> Result<bool, ErrorStack> {
+  let signature = match alg.coordinate_size() {
+    None => signature.to_vec(),
+    Some(size) => {
+      if signature.len() != 2 * size as usize {
+        return Ok(false);
+      }
+      let (r, s) = signature.split_at(size as usize);
+      EcdsaSig::from_private_components(
+        BigNum::from_slice(r)?,
+        BigNum::from_slice(s)?,
+      )?
+      .to_der()?
+    }
+  };
+  match alg.digest() {
+    Some(digest) => {
+      let mut verifier = Verifier::new(digest, key)?;
+      verifier.update(data)?;
+      verifier.verify(&signature)
+    }
+    None => {
+      let mut verifier = Verifier::new_without_digest(key)?;
+      verifier.verify_oneshot(&signature, data)
+    }
+  }
+}
+
+struct Handler {
+  base_url: String,
+  validator: Option<Arc<dyn ChallengeValidator>>,
+  profiles: HashMap<String, String>,
+  orders_page_size: Option<usize>,
+  ca: Ca,
+  state: Mutex<State>,
+}
+
+impl Handler {
+  fn url(&self, path: &str) -> String {
+    format!("{}{}", self.base_url, path)
+  }
+
+  fn new_nonce(&self) -> String {
+    let nonce = random_b64(16).unwrap();
+    self.state.lock().unwrap().nonces.insert(nonce.clone());
+    nonce
+  }
+
+  async fn handle(
+    self: Arc<Self>,
+    req: Request<Body>,
+  ) -> Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path = req
+      .uri()
+      .path_and_query()
+      .map(|path| path.as_str())
+      .unwrap_or("/")
+      .to_string();
+    let is_jose = match req.headers().get(header::CONTENT_TYPE) {
+      Some(value) => value == "application/jose+json",
+      None => false,
+    };
+    let body = hyper::body::to_bytes(req.into_body()).await;
+
+    let res = match (method.clone(), body) {
+      (Method::POST, _) if !is_jose => Err(Problem::new(
+        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        "malformed",
+        "the request content type must be application/jose+json",
+      )),
+      (Method::POST, Ok(body)) => self.handle_post(&path, &body).await,
+      (Method::POST, Err(_)) => {
+        Err(Problem::malformed("failed to read the request body"))
+      }
+      (Method::GET, _) | (Method::HEAD, _) => self.handle_get(&method, &path),
+      _ => Err(Problem::new(
+        StatusCode::METHOD_NOT_ALLOWED,
+        "malformed",
+        "method not allowed",
+      )),
+    };
+    let mut res = res.unwrap_or_else(|problem| {
+      debug!({ path = %path, problem = ?problem }, "Request failed.");
+      problem.into_response()
+    });
+
+    let headers = res.headers_mut();
+    headers.insert(
+      "replay-nonce",
+      header::HeaderValue::from_str(&self.new_nonce()).unwrap(),
+    );
+    headers.insert(
+      header::CACHE_CONTROL,
+      header::HeaderValue::from_static("no-store"),
+    );
+    Ok(res)
+  }
+
+  fn handle_get(
+    &self,
+    method: &Method,
+    path: &str,
+  ) -> Result<Response<Body>, Problem> {
+    if path == "/directory" {
+      return Ok(json_response(StatusCode::OK, &self.directory()));
+    }
+    if path == "/new-nonce" {
+      // RFC 8555 section 7.2: HEAD requests are answered with 200, and GET
+      // requests with 204.
+      let status = if method == Method::HEAD {
+        StatusCode::OK
+      } else {
+        StatusCode::NO_CONTENT
+      };
+      let res = Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap();
+      return Ok(res);
+    }
+    if let Some(id) = path.strip_prefix("/renewal-info/") {
+      return self.renewal_info(id);
+    }
+    Err(Problem::not_found())
+  }
+
+  async fn handle_post(
+    &self,
+    path: &str,
+    body: &[u8],
+  ) -> Result<Response<Body>, Problem> {
+    let req = self.verify_request(path, body)?;
+
+    let (path, query) = match path.split_once('?') {
+      Some((path, query)) => (path, query),
+      None => (path, ""),
+    };
+    let (resource, id) = match path[1..].split_once('/') {
+      Some((resource, id)) => (resource, id),
+      None => (&path[1..], ""),
+    };
+    match resource {
+      "new-account" => self.new_account(&req),
+      "acct" => self.update_account(id, &req),
+      "orders" => self.account_orders(id, query, &req),
+      "key-change" => self.key_change(&req),
+      "new-order" => self.new_order(&req),
+      "order" => self.order(id, &req),
+      "authz" => self.authorization(id, &req),
+      "chall" => self.challenge(id, &req).await,
+      "finalize" => self.finalize(id, &req),
+      "cert" => self.certificate(id, &req),
+      "revoke-cert" => self.revoke_certificate(&req),
+      _ => Err(Problem::not_found()),
+    }
+  }
+
+  fn directory(&self) -> Value {
+    let mut meta = json!({
+      "termsOfService": self.url("/terms"),
+    });
+    if !self.profiles.is_empty() {
+      meta["profiles"] = json!(self.profiles);
+    }
+    json!({
+      "newNonce": self.url("/new-nonce"),
+      "newAccount": self.url("/new-account"),
+      "newOrder": self.url("/new-order"),
+      "revokeCert": self.url("/revoke-cert"),
+      "keyChange": self.url("/key-change"),
+      "renewalInfo": self.url("/renewal-info/"),
+      "meta": meta,
+    })
+  }
+
+  /// Verify the JWS of a request: the signature, the nonce, the URL, and
+  /// the account it was signed by.
+  fn verify_request(
+    &self,
+    path: &str,
+    body: &[u8],
+  ) -> Result<SignedRequest, Problem> {
+    let jws = parse_jws(body)?;
+    let header = &jws.header;
+
+    let mut state = self.state.lock().unwrap();
+    let nonce_valid = match &header.nonce {
+      Some(nonce) => state.nonces.remove(nonce),
+      None => false,
+    };
+    if !nonce_valid {
+      return Err(Problem::new(
+        StatusCode::BAD_REQUEST,
+        "badNonce",
+        "the JWS nonce is invalid",
+      ));
+    }
+    if header.url != self.url(path) {
+      return Err(Problem::unauthorized(
+        "the JWS url does not match the request URL",
+      ));
+    }
+
+    let (account, jwk) = match (&header.kid, &header.jwk) {
+      (Some(kid), None) => {
+        let id = kid
+          .strip_prefix(&self.url("/acct/"))
+          .ok_or_else(|| Problem::malformed("the JWS kid is invalid"))?;
+        let account = state.accounts.get(id).ok_or_else(|| {
+          Problem::new(
+            StatusCode::BAD_REQUEST,
+            "accountDoesNotExist",
+            "the account does not exist",
+          )
+        })?;
+        if account.status != "valid" {
+          return Err(Problem::unauthorized("the account is not valid"));
+        }
+        (Some(id.to_string()), account.jwk.clone())
+      }
+      (None, Some(jwk)) => (None, jwk.clone()),
+      _ => {
+        return Err(Problem::malformed(
+          "the JWS must contain either a kid or a jwk",
+        ))
+      }
+    };
+    drop(state);
+
+    jws.verify(&jwk)?;
+    Ok(SignedRequest {
+      account,
+      jwk,
+      payload: jws.payload,
+    })
+  }
+
+  fn account_json(&self, account: &AccountState, id: &str) -> Value {
+    json!({
+      "status": account.status,
+      "contact": account.contact,
+      "termsOfServiceAgreed": account.terms_of_service_agreed,
+      "orders": self.url(&format!("/orders/{}", id)),
+    })
+  }
+
+  fn new_account(
+    &self,
+    req: &SignedRequest,
+  ) -> Result<Response<Body>, Problem> {
+    if req.account.is_some() {
+      return Err(Problem::malformed("newAccount must be signed with a jwk"));
+    }
+    #[derive(Deserialize, Default)]
+    #[serde(rename_all = "camelCase")]
+    struct Payload {
+      contact: Option<Vec<String>>,
+      terms_of_service_agreed: Option<bool>,
+      only_return_existing: Option<bool>,
+    }
+    let payload: Payload = req.json()?.unwrap_or_default();
+
+    let mut state = self.state.lock().unwrap();
+    let existing = state
+      .accounts
+      .iter()
+      .find(|(_, account)| account.jwk == req.jwk);
+    if let Some((id, account)) = existing {
+      if account.status != "valid" {
+        return Err(Problem::unauthorized("the account is not valid"));
+      }
+      let res = json_response(StatusCode::OK, &self.account_json(account, id));
+      return Ok(with_location(res, &self.url(&format!("/acct/{}", id))));
+    }
+    if payload.only_return_existing == Some(true) {
+      return Err(Problem::new(
+        StatusCode::BAD_REQUEST,
+        "accountDoesNotExist",
+        "no account exists for this key",
+      ));
+    }
+
+    let id = state.next_id();
+    let account = AccountState {
+      jwk: req.jwk.clone(),
+      status: "valid",
+      contact: payload.contact.unwrap_or_default(),
+      terms_of_service_agreed: payload.terms_of_service_agreed.unwrap_or(false),
+      orders: vec![],
+    };
+    let res =
+      json_response(StatusCode::CREATED, &self.account_json(&account, &id));
+    state.accounts.insert(id.clone(), account);
+    debug!({ id = %id }, "Created account.");
+    Ok(with_location(res, &self.url(&format!("/acct/{}", id))))
+  }
+
+  fn update_account(
+    &self,
+    id: &str,
+    req: &SignedRequest,
+  ) -> Result<Response<Body>, Problem> {
+    if req.account()? != id {
+      return Err(Problem::unauthorized("the account does not match the kid"));
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Payload {
+      contact: Option<Vec<String>>,
+      terms_of_service_agreed: Option<bool>,
+      status: Option<String>,
+    }
+    let payload: Option<Payload> = req.json()?;
+
+    let mut state = self.state.lock().unwrap();
+    let account = state.accounts.get_mut(id).unwrap();
+    if let Some(payload) = payload {
+      if let Some(contact) = payload.contact {
+        account.contact = contact;
+      }
+      if let Some(agreed) = payload.terms_of_service_agreed {
+        account.terms_of_service_agreed = agreed;
+      }
+      match payload.status.as_deref() {
+        None => {}
+        Some("deactivated") => account.status = "deactivated",
+        Some(_) => {
+          return Err(Problem::malformed(
+            "the account status can only be changed to deactivated",
+          ))
+        }
+      }
+    }
+    Ok(json_response(
+      StatusCode::OK,
+      &self.account_json(account, id),
+    ))
+  }
+
+  fn account_orders(
+    &self,
+    id: &str,
+    query: &str,
+    req: &SignedRequest,
+  ) -> Result<Response<Body>, Problem> {
+    if req.account()? != id {
+      return Err(Problem::unauthorized("the account does not match the kid"));
+    }
+    let page: usize = match query.strip_prefix("page=") {
+      Some(page) => page
+        .parse()
+        .map_err(|_| Problem::malformed("the page is malformed"))?,
+      None if query.is_empty() => 0,
+      None => return Err(Problem::not_found()),
+    };
+
+    let state = self.state.lock().unwrap();
+    let orders = &state.accounts[id].orders;
+    let page_size = self.orders_page_size.unwrap_or(usize::MAX);
+    let start = page.saturating_mul(page_size).min(orders.len());
+    let end = start.saturating_add(page_size).min(orders.len());
+    let urls: Vec<String> = orders[start..end]
+      .iter()
+      .map(|order| self.url(&format!("/order/{}", order)))
+      .collect();
+    let mut res = json_response(StatusCode::OK, &json!({ "orders": urls }));
+    if end < orders.len() {
+      let next = format!("</orders/{}?page={}>;rel=\"next\"", id, page + 1);
+      res
+        .headers_mut()
+        .append(header::LINK, header::HeaderValue::from_str(&next).unwrap());
+    }
+    Ok(res)
+  }
+
+  fn key_change(&self, req: &SignedRequest) -> Result<Response<Body>, Problem> {
+    let id = req.account()?;
+    let inner = parse_jws(&req.payload)?;
+    let header = &inner.header;
+    let new_jwk = header
+      .jwk
+      .clone()
+      .ok_or_else(|| Problem::malformed("the inner JWS must contain a jwk"))?;
+    inner.verify(&new_jwk)?;
+    if header.kid.is_some() || header.nonce.is_some() {
+      return Err(Problem::malformed(
+        "the inner JWS must not contain a kid or nonce",
+      ));
+    }
+    if header.url != self.url("/key-change") {
+      return Err(Problem::malformed(
+        "the inner JWS url does not match the request URL",
+      ));
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Payload {
+      account: String,
+      old_key: Jwk,
+    }
+    let payload: Payload = serde_json::from_slice(&inner.payload)
+      .map_err(|_| Problem::malformed("the inner JWS payload is malformed"))?;
+
+    let mut state = self.state.lock().unwrap();
+    if payload.account != self.url(&format!("/acct/{}", id)) {
+      return Err(Problem::unauthorized("the account does not match the kid"));
+    }
+    if payload.old_key != req.jwk {
+      return Err(Problem::unauthorized(
+        "the old key does not match the account key",
+      ));
+    }
+    let conflict = state
+      .accounts
+      .iter()
+      .find(|(_, account)| account.jwk == new_jwk);
+    if let Some((other, _)) = conflict {
+      let problem = Problem::new(
+        StatusCode::CONFLICT,
+        "malformed",
+        "the new key is already in use by another account",
+      );
+      let location = self.url(&format!("/acct/{}", other));
+      return Ok(with_location(problem.into_response(), &location));
+    }
+
+    let account = state.accounts.get_mut(id).unwrap();
+    account.jwk = new_jwk;
+    debug!({ id = %id }, "Rolled over account key.");
+    Ok(json_response(
+      StatusCode::OK,
+      &self.account_json(account, id),
+    ))
+  }
+
+  fn new_order(&self, req: &SignedRequest) -> Result<Response<Body>, Problem> {
+    let account = req.account()?.to_string();
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Payload {
+      identifiers: Vec<Identifier>,
+      not_before: Option<String>,
+      not_after: Option<String>,
+      profile: Option<String>,
+      replaces: Option<String>,
+    }
+    let payload: Payload = req
+      .json()?
+      .ok_or_else(|| Problem::malformed("the request payload is empty"))?;
+
+    if payload.identifiers.is_empty() {
+      return Err(Problem::malformed("the order has no identifiers"));
+    }
+    let identifiers = payload
+      .identifiers
+      .iter()
+      .map(normalize_identifier)
+      .collect::<Result<Vec<_>, _>>()?;
+    let parse_time = |time: &Option<String>| match time {
+      Some(time) => OffsetDateTime::parse(time, &Rfc3339)
+        .map(Some)
+        .map_err(|_| Problem::malformed("notBefore or notAfter is malformed")),
+      None => Ok(None),
+    };
+    let not_before = parse_time(&payload.not_before)?;
+    let not_after = parse_time(&payload.not_after)?;
+    if let (Some(not_before), Some(not_after)) = (not_before, not_after) {
+      if not_after <= not_before {
+        return Err(Problem::malformed("notAfter must be after notBefore"));
+      }
+    }
+    if let Some(not_after) = not_after {
+      if not_after - not_before.unwrap_or_else(now) > CERTIFICATE_LIFETIME {
+        return Err(Problem::malformed(
+          "the requested notAfter exceeds the maximum certificate lifetime",
+        ));
+      }
+    }
+    if let Some(profile) = &payload.profile {
+      if !self.profiles.contains_key(profile) {
+        return Err(Problem::new(
+          StatusCode::BAD_REQUEST,
+          "invalidProfile",
+          "the requested profile is not offered",
+        ));
+      }
+    }
+
+    let mut state = self.state.lock().unwrap();
+    let expires = now() + time::Duration::days(7);
+    let mut authorizations = vec![];
+    let unique: BTreeSet<_> = identifiers
+      .iter()
+      .map(|identifier| (identifier.r#type.clone(), identifier.value.clone()))
+      .collect();
+    for (r#type, value) in unique {
+      let id = state.next_id();
+      let wildcard = value.starts_with("*.");
+      let identifier = Identifier {
+        r#type: r#type.clone(),
+        value: value.trim_start_matches("*.").to_string(),
+      };
+      let types: &[&'static str] = if wildcard {
+        &["dns-01"]
+      } else if r#type == "ip" {
+        &["http-01", "tls-alpn-01"]
+      } else {
+        &["http-01", "dns-01", "tls-alpn-01"]
+      };
+      let mut challenges = vec![];
+      for r#type in types {
+        let challenge_id = state.next_id();
+        state.challenges.insert(challenge_id.clone(), id.clone());
+        challenges.push(ChallengeState {
+          id: challenge_id,
+          r#type,
+          status: "pending",
+          token: random_b64(32)?,
+          validated: None,
+          error: None,
+        });
+      }
+      state.authorizations.insert(
+        id.clone(),
+        AuthorizationState {
+          account: account.clone(),
+          status: "pending",
+          expires,
+          identifier,
+          wildcard,
+          challenges,
+        },
+      );
+      authorizations.push(id);
+    }
+
+    let id = state.next_id();
+    let order = OrderState {
+      account: account.clone(),
+      status: "pending",
+      expires,
+      identifiers,
+      not_before,
+      not_after,
+      profile: payload.profile,
+      replaces: payload.replaces,
+      authorizations,
+      certificate: None,
+    };
+    let res = json_response(StatusCode::CREATED, &self.order_json(&order, &id));
+    state.orders.insert(id.clone(), order);
+    state
+      .accounts
+      .get_mut(&account)
+      .unwrap()
+      .orders
+      .push(id.clone());
+    debug!({ id = %id }, "Created order.");
+    Ok(with_location(res, &self.url(&format!("/order/{}", id))))
+  }
+
+  fn order_json(&self, order: &OrderState, id: &str) -> Value {
+    let mut value = json!({
+      "status": order.status,
+      "expires": format_time(order.expires),
+      "identifiers": order.identifiers,
+      "authorizations": order
+        .authorizations
+        .iter()
+        .map(|authorization| self.url(&format!("/authz/{}", authorization)))
+        .collect::<Vec<_>>(),
+      "finalize": self.url(&format!("/finalize/{}", id)),
+    });
+    if let Some(not_before) = order.not_before {
+      value["notBefore"] = json!(format_time(not_before));
+    }
+    if let Some(not_after) = order.not_after {
+      value["notAfter"] = json!(format_time(not_after));
+    }
+    if let Some(profile) = &order.profile {
+      value["profile"] = json!(profile);
+    }
+    if let Some(replaces) = &order.replaces {
+      value["replaces"] = json!(replaces);
+    }
+    if let Some(certificate) = &order.certificate {
+      value["certificate"] = json!(self.url(&format!("/cert/{}", certificate)));
+    }
+    if order.status == "invalid" {
+      value["error"] = json!({
+        "type": format!("{}unauthorized", PROBLEM_PREFIX),
+        "detail": "an authorization of the order is not valid",
+      });
+    }
+    value
+  }
+
+  /// Move a pending order to ready or invalid, depending on the state of
+  /// its authorizations.
+  fn update_order_status(state: &mut State, id: &str) {
+    let order = &state.orders[id];
+    if order.status != "pending" {
+      return;
+    }
+    let statuses: Vec<_> = order
+      .authorizations
+      .iter()
+      .map(|authorization| state.authorizations[authorization].status)
+      .collect();
+    let status = if statuses
+      .iter()
+      .any(|status| *status != "pending" && *status != "valid")
+    {
+      "invalid"
+    } else if statuses.iter().all(|status| *status == "valid") {
+      "ready"
+    } else {
+      "pending"
+    };
+    state.orders.get_mut(id).unwrap().status = status;
+  }
+
+  fn order(
+    &self,
+    id: &str,
+    req: &SignedRequest,
+  ) -> Result<Response<Body>, Problem> {
+    let account = req.account()?;
+    let mut state = self.state.lock().unwrap();
+    match state.orders.get(id) {
+      Some(order) if order.account == account => {}
+      Some(_) => {
+        return Err(Problem::unauthorized(
+          "the order belongs to another account",
+        ))
+      }
+      None => return Err(Problem::not_found()),
+    }
+    Self::update_order_status(&mut state, id);
+    Ok(json_response(
+      StatusCode::OK,
+      &self.order_json(&state.orders[id], id),
+    ))
+  }
+
+  fn challenge_json(&self, challenge: &ChallengeState) -> Value {
+    let mut value = json!({
+      "type": challenge.r#type,
+      "url": self.url(&format!("/chall/{}", challenge.id)),
+      "status": challenge.status,
+      "token": challenge.token,
+    });
+    if let Some(validated) = challenge.validated {
+      value["validated"] = json!(format_time(validated));
+    }
+    if let Some(error) = &challenge.error {
+      value["error"] = error.clone();
+    }
+    value
+  }
+
+  fn authorization_json(&self, authorization: &AuthorizationState) -> Value {
+    json!({
+      "identifier": authorization.identifier,
+      "status": authorization.status,
+      "expires": format_time(authorization.expires),
+      "challenges": authorization
+        .challenges
+        .iter()
+        .map(|challenge| self.challenge_json(challenge))
+        .collect::<Vec<_>>(),
+      "wildcard": authorization.wildcard,
+    })
+  }
+
+  fn authorization(
+    &self,
+    id: &str,
+    req: &SignedRequest,
+  ) -> Result<Response<Body>, Problem> {
+    let account = req.account()?;
+    #[derive(Deserialize)]
+    struct Payload {
+      status: Option<String>,
+    }
+    let payload: Option<Payload> = req.json()?;
+
+    let mut state = self.state.lock().unwrap();
+    let authorization = match state.authorizations.get_mut(id) {
+      Some(authorization) if authorization.account == account => authorization,
+      Some(_) => {
+        return Err(Problem::unauthorized(
+          "the authorization belongs to another account",
+        ))
+      }
+      None => return Err(Problem::not_found()),
+    };
+    match payload.and_then(|payload| payload.status).as_deref() {
+      None => {}
+      Some("deactivated") => authorization.status = "deactivated",
+      Some(_) => {
+        return Err(Problem::malformed(
+          "the authorization status can only be changed to deactivated",
+        ))
+      }
+    }
+    Ok(json_response(
+      StatusCode::OK,
+      &self.authorization_json(authorization),
+    ))
+  }
+
+  async fn challenge(
+    &self,
+    id: &str,
+    req: &SignedRequest,
+  ) -> Result<Response<Body>, Problem> {
+    let account = req.account()?;
+    let payload: Option<Value> = req.json()?;
+
+    let request = {
+      let mut state = self.state.lock().unwrap();
+      let authorization_id = state
+        .challenges
+        .get(id)
+        .cloned()
+        .ok_or_else(Problem::not_found)?;
+      let jwk = state.accounts[account].jwk.clone();
+      let authorization =
+        state.authorizations.get_mut(&authorization_id).unwrap();
+      if authorization.account != account {
+        return Err(Problem::unauthorized(
+          "the challenge belongs to another account",
+        ));
+      }
+      let authorization_pending = authorization.status == "pending";
+      let identifier = authorization.identifier.clone();
+      let challenge = authorization
+        .challenges
+        .iter_mut()
+        .find(|challenge| challenge.id == id)
+        .unwrap();
+
+      // POST-as-GET requests only return the challenge, other requests ask
+      // the server to validate it.
+      if payload.is_none() || challenge.status != "pending" {
+        return Ok(json_response(
+          StatusCode::OK,
+          &self.challenge_json(challenge),
+        ));
+      }
+      if !authorization_pending {
+        return Err(Problem::malformed("the authorization is not pending"));
+      }
+      challenge.status = "processing";
+      ValidationRequest {
+        identifier,
+        r#type: challenge.r#type.to_string(),
+        token: challenge.token.clone(),
+        key_authorization: format!("{}.{}", challenge.token, jwk.thumbprint()?),
+      }
+    };
+
+    let result = match &self.validator {
+      Some(validator) => validator.validate(&request).await,
+      None => Ok(()),
+    };
+    debug!({ id = %id, result = ?result }, "Validated challenge.");
+
+    let mut state = self.state.lock().unwrap();
+    let authorization_id = state.challenges[id].clone();
+    let authorization =
+      state.authorizations.get_mut(&authorization_id).unwrap();
+    let challenge = authorization
+      .challenges
+      .iter_mut()
+      .find(|challenge| challenge.id == id)
+      .unwrap();
+    match result {
+      Ok(()) => {
+        challenge.status = "valid";
+        challenge.validated = Some(now());
+        authorization.status = "valid";
+      }
+      Err(detail) => {
+        challenge.status = "invalid";
+        challenge.error = Some(json!({
+          "type": format!("{}incorrectResponse", PROBLEM_PREFIX),
+          "detail": detail,
+          "status": 403,
+        }));
+        authorization.status = "invalid";
+      }
+    }
+    Ok(json_response(
+      StatusCode::OK,
+      &self.challenge_json(challenge),
+    ))
+  }
+
+  fn finalize(
+    &self,
+    id: &str,
+    req: &SignedRequest,
+  ) -> Result<Response<Body>, Problem> {
+    let account = req.account()?;
+    #[derive(Deserialize)]
+    struct Payload {
+      csr: String,
+    }
+    let payload: Payload = req
+      .json()?
+      .ok_or_else(|| Problem::malformed("the request payload is empty"))?;
+    let csr = b64_decode(&payload.csr)
+      .ok()
+      .and_then(|der| X509Req::from_der(&der).ok())
+      .ok_or_else(|| bad_csr("the CSR is malformed"))?;
+
+    let mut state = self.state.lock().unwrap();
+    match state.orders.get(id) {
+      Some(order) if order.account == account => {}
+      Some(_) => {
+        return Err(Problem::unauthorized(
+          "the order belongs to another account",
+        ))
+      }
+      None => return Err(Problem::not_found()),
+    }
+    Self::update_order_status(&mut state, id);
+    let order = &state.orders[id];
+    if order.status != "ready" {
+      return Err(Problem::new(
+        StatusCode::FORBIDDEN,
+        "orderNotReady",
+        "the order is not ready",
+      ));
+    }
+    check_csr(&csr, &order.identifiers)?;
+
+    let not_before = order.not_before.unwrap_or_else(now);
+    let not_after =
+      order.not_after.unwrap_or(not_before + CERTIFICATE_LIFETIME);
+    let certificate =
+      self
+        .ca
+        .issue(&csr, &order.identifiers, not_before, not_after)?;
+    let certificate_id = state.next_id();
+    state.certificates.insert(
+      certificate_id.clone(),
+      CertificateState {
+        account: account.to_string(),
+        certificate,
+        revoked: false,
+      },
+    );
+    let order = state.orders.get_mut(id).unwrap();
+    order.status = "valid";
+    order.certificate = Some(certificate_id);
+    debug!({ id = %id }, "Issued certificate.");
+    Ok(json_response(StatusCode::OK, &self.order_json(order, id)))
+  }
+
+  fn certificate(
+    &self,
+    id: &str,
+    req: &SignedRequest,
+  ) -> Result<Response<Body>, Problem> {
+    let account = req.account()?;
+    // The default chain is served at `/cert/<id>`, and the alternate chain
+    // at `/cert/<id>/1`.
+    let (id, alternate) = match id.split_once('/') {
+      None => (id, false),
+      Some((id, "1")) => (id, true),
+      Some(_) => return Err(Problem::not_found()),
+    };
+    let state = self.state.lock().unwrap();
+    let certificate = match state.certificates.get(id) {
+      Some(certificate) if certificate.account == account => certificate,
+      Some(_) => {
+        return Err(Problem::unauthorized(
+          "the certificate belongs to another account",
+        ))
+      }
+      None => return Err(Problem::not_found()),
+    };
+    let chain_pem = self.ca.chain_pem(&certificate.certificate, alternate)?;
+    let mut res = Response::builder()
+      .header(header::CONTENT_TYPE, "application/pem-certificate-chain")
+      .body(Body::from(chain_pem))
+      .unwrap();
+    if !alternate {
+      let link = format!(
+        "<{}>;rel=\"alternate\"",
+        self.url(&format!("/cert/{}/1", id))
+      );
+      res
+        .headers_mut()
+        .append(header::LINK, header::HeaderValue::from_str(&link).unwrap());
+    }
+    Ok(res)
+  }
+
+  fn revoke_certificate(
+    &self,
+    req: &SignedRequest,
+  ) -> Result<Response<Body>, Problem> {
+    #[derive(Deserialize)]
+    struct Payload {
+      certificate: String,
+      reason: Option<u8>,
+    }
+    let payload: Payload = req
+      .json()?
+      .ok_or_else(|| Problem::malformed("the request payload is empty"))?;
+    if let Some(reason) = payload.reason {
+      if reason == 7 || reason > 10 {
+        return Err(Problem::new(
+          StatusCode::BAD_REQUEST,
+          "badRevocationReason",
+          "the revocation reason is not allowed",
+        ));
+      }
+    }
+    let der = b64_decode(&payload.certificate)
+      .map_err(|_| Problem::malformed("the certificate is malformed"))?;
+
+    let mut state = self.state.lock().unwrap();
+    let certificate = state
+      .certificates
+      .values_mut()
+      .find(|certificate| {
+        certificate.certificate.to_der().ok().as_ref() == Some(&der)
+      })
+      .ok_or_else(Problem::not_found)?;
+
+    let authorized = match &req.account {
+      Some(account) => *account == certificate.account,
+      None => {
+        let key = jwk_public_key(&req.jwk)?;
+        certificate.certificate.public_key()?.public_eq(&key)
+      }
+    };
+    if !authorized {
+      return Err(Problem::unauthorized(
+        "the requester is not allowed to revoke the certificate",
+      ));
+    }
+    if certificate.revoked {
+      return Err(Problem::new(
+        StatusCode::BAD_REQUEST,
+        "alreadyRevoked",
+        "the certificate is already revoked",
+      ));
+    }
+    certificate.revoked = true;
+    debug!("Revoked certificate.");
+    Ok(Response::new(Body::empty()))
+  }
+
+  fn renewal_info(&self, id: &str) -> Result<Response<Body>, Problem> {
+    let state = self.state.lock().unwrap();
+    let certificate = state
+      .certificates
+      .values()
+      .find(|certificate| {
+        certificate_identifier(&certificate.certificate)
+          .ok()
+          .as_deref()
+          == Some(id)
+      })
+      .ok_or_else(Problem::not_found)?;
+
+    // Suggest renewing in the last third of the lifetime, or immediately if
+    // the certificate was revoked.
+    let (start, end) = if certificate.revoked {
+      let now = now();
+      (
+        now - time::Duration::hours(2),
+        now - time::Duration::hours(1),
+      )
+    } else {
+      let not_before =
+        asn1_time_to_offset(certificate.certificate.not_before());
+      let not_after = asn1_time_to_offset(certificate.certificate.not_after());
+      let lifetime = not_after - not_before;
+      (not_before + lifetime * 2 / 3, not_before + lifetime * 5 / 6)
+    };
+    let mut res = json_response(
+      StatusCode::OK,
+      &json!({
+        "suggestedWindow": {
+          "start": format_time(start),
+          "end": format_time(end),
+        }
+      }),
+    );
+    res.headers_mut().insert(
+      header::RETRY_AFTER,
+      header::HeaderValue::from_static("21600"),
+    );
+    Ok(res)
+  }
+}
+
+fn bad_csr(detail: &str) -> Problem {
+  Problem::new(StatusCode::BAD_REQUEST, "badCSR", detail)
+}
+
+/// Validate and normalize an identifier of a new order.
+fn normalize_identifier(
+  identifier: &Identifier,
+) -> Result<Identifier, Problem> {
+  let rejected = |detail| {
+    Problem::new(StatusCode::BAD_REQUEST, "rejectedIdentifier", detail)
+  };
+  match identifier.r#type.as_str() {
+    "dns" => {
+      let value = normalize_name(&identifier.value);
+      let name = value.strip_prefix("*.").unwrap_or(&value);
+      let valid = !name.is_empty()
+        && name.split('.').all(|label| {
+          !label.is_empty()
+            && label.len() <= 63
+            && label
+              .bytes()
+              .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        });
+      if !valid {
+        return Err(rejected("the domain name is invalid"));
+      }
+      Ok(Identifier::dns(value))
+    }
+    "ip" => {
+      let ip: IpAddr = identifier
+        .value
+        .parse()
+        .map_err(|_| rejected("the ip address is invalid"))?;
+      Ok(Identifier::ip(ip))
+    }
+    _ => Err(Problem::new(
+      StatusCode::BAD_REQUEST,
+      "unsupportedIdentifier",
+      "the identifier type is not supported",
+    )),
+  }
+}
+
+/// Check that the CSR is signed by its key, and requests exactly the
+/// identifiers of the order.
+fn check_csr(csr: &X509Req, identifiers: &[Identifier]) -> Result<(), Problem> {
+  let key = csr.public_key()?;
+  if !csr.verify(&key)? {
+    return Err(bad_csr("the CSR signature is invalid"));
+  }
+
+  // OpenSSL can only parse the SANs of certificates, so copy the extensions
+  // of the CSR into an unsigned certificate.
+  let mut builder = X509::builder()?;
+  if let Ok(extensions) = csr.extensions() {
+    for extension in extensions {
+      builder.append_extension(extension)?;
+    }
+  }
+  let certificate = builder.build();
+
+  let mut requested = BTreeSet::new();
+  for name in certificate.subject_alt_names().into_iter().flatten() {
+    if let Some(dns) = name.dnsname() {
+      requested.insert(("dns", dns.to_lowercase()));
+    } else if let Some(ip) = name.ipaddress() {
+      let ip = match ip.len() {
+        4 => IpAddr::from(<[u8; 4]>::try_from(ip).unwrap()),
+        16 => IpAddr::from(<[u8; 16]>::try_from(ip).unwrap()),
+        _ => return Err(bad_csr("the CSR contains an invalid ip address")),
+      };
+      requested.insert(("ip", ip.to_string()));
+    } else {
+      return Err(bad_csr("the CSR contains an unsupported SAN"));
+    }
+  }
+  let expected: BTreeSet<_> = identifiers
+    .iter()
+    .map(|identifier| {
+      let r#type = if identifier.is_ip() { "ip" } else { "dns" };
+      (r#type, identifier.value.clone())
+    })
+    .collect();
+  if requested != expected {
+    return Err(bad_csr(
+      "the CSR does not request exactly the identifiers of the order",
+    ));
+  }
+
+  let common_name = csr
+    .subject_name()
+    .entries_by_nid(Nid::COMMONNAME)
+    .next()
+    .and_then(|entry| entry.data().to_string().ok());
+  if let Some(common_name) = common_name {
+    if !expected.contains(&("dns", common_name.to_lowercase())) {
+      return Err(bad_csr(
+        "the CSR common name is not an identifier of the order",
+      ));
+    }
+  }
+  Ok(())
+}
+
+fn asn1_time_to_offset(time: &openssl::asn1::Asn1TimeRef) -> OffsetDateTime {
+  let epoch = Asn1Time::from_unix(0).unwrap();
+  let diff = epoch.diff(time).unwrap();
+  OffsetDateTime::UNIX_EPOCH
+    + time::Duration::days(diff.days as i64)
+    + time::Duration::seconds(diff.secs as i64)
+}
@@ -0,0 +1,168 @@
+use crate::authorization::Challenge;
+use crate::authorization::ACME_TLS_ALPN_PROTOCOL;
+use crate::error::*;
+use openssl::pkey::PKey;
+use openssl::pkey::Private;
+use openssl::ssl::AlpnError;
+use openssl::ssl::NameType;
+use openssl::ssl::SslContextBuilder;
+use openssl::ssl::SslRef;
+use openssl::x509::X509;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::RwLock;
+use tracing::debug;
+use tracing::warn;
+
+/// A validation certificate together with its private key.
+type ValidationCertificate = (PKey<Private>, X509);
+
+/// A responder for TLS-ALPN01 challenges.
+///
+/// The responder holds the validation certificates for the registered
+/// [`Challenge`]s, keyed by the SNI name the ACME server will request. It is
+/// cheap to clone, and all clones share the same certificates, so challenges
+/// can be registered while a TLS server configured with the responder is
+/// running.
+///
+/// Use [`TlsAlpn01Responder::configure`] to install the responder into an
+/// OpenSSL server context. Handshakes that negotiate the
+/// [`ACME_TLS_ALPN_PROTOCOL`] are answered with the validation certificate
+/// for the requested SNI name, and all other handshakes continue with the
+/// application certificate configured on the context.
+///
+/// [`ACME_TLS_ALPN_PROTOCOL`]: crate::ACME_TLS_ALPN_PROTOCOL
+#[derive(Clone, Debug, Default)]
+pub struct TlsAlpn01Responder {
+  certs: Arc<RwLock<HashMap<String, ValidationCertificate>>>,
+}
+
+impl TlsAlpn01Responder {
+  /// Create a new responder without any registered challenges.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Register a TLS-ALPN01 challenge, so that its validation certificate is
+  /// served for the identifier of the challenge.
+  pub fn register(&self, challenge: &Challenge) -> Result<(), Error> {
+    if challenge.r#type != "tls-alpn-01" {
+      return Err(Error::Validation(
+        "only tls-alpn-01 challenges can be registered with the tls-alpn-01 responder",
+      ));
+    }
+    let server_name = server_name(challenge)?;
+    let cert = challenge
+      .tls_alpn01_certificate()?
+      .ok_or(Error::Validation("the challenge does not have a token"))?;
+
+    self.certs.write().unwrap().insert(server_name, cert);
+    Ok(())
+  }
+
+  /// Remove a previously registered challenge.
+  pub fn remove(&self, challenge: &Challenge) {
+    if let Ok(server_name) = server_name(challenge) {
+      self.certs.write().unwrap().remove(&server_name);
+    }
+  }
+
+  /// Install the responder into an OpenSSL server context.
+  ///
+  /// This replaces the ALPN selection callback of the context. `protocols`
+  /// are the ALPN protocols of the application in wire format (see
+  /// [`SslContextBuilder::set_alpn_protos`]), in order of preference. These
+  /// are negotiated for all connections that do not request the
+  /// [`ACME_TLS_ALPN_PROTOCOL`].
+  ///
+  /// [`ACME_TLS_ALPN_PROTOCOL`]: crate::ACME_TLS_ALPN_PROTOCOL
+  pub fn configure(&self, builder: &mut SslContextBuilder, protocols: &[u8]) {
+    let responder = self.clone();
+    let protocols = protocols.to_vec();
+    builder.set_alpn_select_callback(move |ssl, client| {
+      if select_acme_protocol(client).is_some() {
+        return responder.answer(ssl, client);
+      }
+      select_protocol(&protocols, client).ok_or(AlpnError::NOACK)
+    });
+  }
+
+  /// Answer a handshake that requested the `acme-tls/1` protocol by
+  /// swapping in the validation certificate for the requested SNI name. The
+  /// certificate is only selected after ALPN negotiation, so setting it here
+  /// takes effect for this handshake.
+  fn answer<'a>(
+    &self,
+    ssl: &mut SslRef,
+    client: &'a [u8],
+  ) -> Result<&'a [u8], AlpnError> {
+    let server_name = ssl
+      .servername(NameType::HOST_NAME)
+      .map(|name| name.to_lowercase());
+    let server_name = match server_name {
+      Some(server_name) => server_name,
+      None => {
+        warn!("Rejecting tls-alpn-01 handshake without SNI.");
+        return Err(AlpnError::ALERT_FATAL);
+      }
+    };
+
+    let certs = self.certs.read().unwrap();
+    let (pkey, cert) = match certs.get(&server_name) {
+      Some(cert) => cert,
+      None => {
+        warn!(
+          { server_name = %server_name },
+          "Rejecting tls-alpn-01 handshake for unknown server name."
+        );
+        return Err(AlpnError::ALERT_FATAL);
+      }
+    };
+    ssl
+      .set_certificate(cert)
+      .and_then(|_| ssl.set_private_key(pkey))
+      .map_err(|_| AlpnError::ALERT_FATAL)?;
+
+    debug!(
+      { server_name = %server_name },
+      "Answering tls-alpn-01 challenge handshake."
+    );
+    select_acme_protocol(client).ok_or(AlpnError::ALERT_FATAL)
+  }
+}
+
+fn server_name(challenge: &Challenge) -> Result<String, Error> {
+  let identifier = challenge.identifier.as_ref().ok_or(Error::Validation(
+    "the challenge was not retrieved through an authorization",
+  ))?;
+  Ok(identifier.tls_server_name().to_lowercase())
+}
+
+/// Iterate over the protocols in an ALPN wire format protocol list.
+fn protocols(mut list: &[u8]) -> impl Iterator<Item = &[u8]> {
+  std::iter::from_fn(move || {
+    let (&len, rest) = list.split_first()?;
+    if rest.len() < len as usize {
+      return None;
+    }
+    let (protocol, rest) = rest.split_at(len as usize);
+    list = rest;
+    Some(protocol)
+  })
+}
+
+/// Select the [`ACME_TLS_ALPN_PROTOCOL`] if the client offered it, returning
+/// it as a slice of `client`.
+fn select_acme_protocol(client: &[u8]) -> Option<&[u8]> {
+  protocols(client).find(|offered| *offered == ACME_TLS_ALPN_PROTOCOL)
+}
+
+/// Select the first protocol in `server` that is also offered by the client,
+/// returning it as a slice of `client`.
+pub(crate) fn select_protocol<'a>(
+  server: &[u8],
+  client: &'a [u8],
+) -> Option<&'a [u8]> {
+  protocols(server)
+    .find_map(|wanted| protocols(client).find(|offered| *offered == wanted))
+}
@@ -0,0 +1,221 @@
+use crate::authorization::Authorization;
+use crate::authorization::Challenge;
+use crate::error::*;
+use crate::self_check::Http01SelfCheck;
+use crate::solver::ChallengeSolver;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tokio::io::AsyncWriteExt;
+use tracing::debug;
+use tracing::warn;
+
+/// The directory below the webroot that challenge files are written to.
+const CHALLENGE_DIR: &str = ".well-known/acme-challenge";
+
+/// A [`ChallengeSolver`] for HTTP01 challenges, that writes the key
+/// authorization into the directory served by an existing web server (for
+/// example nginx or Apache).
+///
+/// The key authorization is written to
+/// `<webroot>/.well-known/acme-challenge/<token>`, and removed again during
+/// cleanup. Files that have not been cleaned up yet are removed when the
+/// solver is dropped, so they do not outlive a panicking or cancelled
+/// order.
+#[derive(Debug)]
+pub struct WebrootSolver {
+  webroot: PathBuf,
+  files: Mutex<HashMap<String, ChallengeFile>>,
+  self_check: Option<Http01SelfCheck>,
+}
+
+impl WebrootSolver {
+  /// Create a solver that writes challenge files below `webroot`.
+  pub fn new(webroot: PathBuf) -> Self {
+    WebrootSolver {
+      webroot,
+      files: Mutex::new(HashMap::new()),
+      self_check: None,
+    }
+  }
+
+  /// Check that the challenge file is reachable through the web server
+  /// after writing it, before the ACME server is asked to validate the
+  /// challenge.
+  pub fn self_check(&mut self, self_check: Http01SelfCheck) -> &mut Self {
+    self.self_check = Some(self_check);
+    self
+  }
+
+  /// The path of the challenge file for the challenge.
+  pub fn challenge_path(
+    &self,
+    challenge: &Challenge,
+  ) -> Result<PathBuf, Error> {
+    let token = challenge
+      .token
+      .as_deref()
+      .ok_or(Error::Validation("the challenge does not have a token"))?;
+    // Tokens are base64url encoded (RFC 8555 section 8.1), which also makes
+    // them safe file names. Reject anything else, so a malicious server can
+    // not write outside of the challenge directory.
+    let is_safe = !token.is_empty()
+      && token
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if !is_safe {
+      return Err(Error::Validation(
+        "the challenge token is not a safe file name",
+      ));
+    }
+    Ok(self.webroot.join(CHALLENGE_DIR).join(token))
+  }
+}
+
+#[async_trait]
+impl ChallengeSolver for WebrootSolver {
+  fn supported_types(&self) -> &[&str] {
+    &["http-01"]
+  }
+
+  async fn present(
+    &self,
+    _authorization: &Authorization,
+    challenge: &Challenge,
+  ) -> Result<(), Error> {
+    let path = self.challenge_path(challenge)?;
+    let key_authorization = challenge
+      .key_authorization()?
+      .ok_or(Error::Validation("the challenge does not have a token"))?;
+
+    let dir = path.parent().unwrap();
+    create_dir_all(dir).await?;
+
+    // Write to a temporary file first, so the web server never serves a
+    // partially written file.
+    let mut suffix = [0; 8];
+    openssl::rand::rand_bytes(&mut suffix)?;
+    let tmp_path = dir.join(format!(
+      ".{}.{}.tmp",
+      path.file_name().unwrap().to_string_lossy(),
+      suffix
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>()
+    ));
+    let tmp = ChallengeFile::new(tmp_path);
+    write_file(&tmp.path, key_authorization.as_bytes()).await?;
+    map_io_err(tokio::fs::rename(&tmp.path, &path).await)?;
+    tmp.disarm();
+
+    debug!({ path = %path.display() }, "Wrote http-01 challenge file.");
+    let previous = self
+      .files
+      .lock()
+      .unwrap()
+      .insert(challenge.token.clone().unwrap(), ChallengeFile::new(path));
+    // Presenting the same challenge again replaced the file in place, so the
+    // previous guard must not remove it.
+    if let Some(previous) = previous {
+      previous.disarm();
+    }
+
+    if let Some(self_check) = &self.self_check {
+      self_check.check(challenge).await?;
+    }
+    Ok(())
+  }
+
+  async fn cleanup(
+    &self,
+    _authorization: &Authorization,
+    challenge: &Challenge,
+  ) -> Result<(), Error> {
+    let path = self.challenge_path(challenge)?;
+    let file = self
+      .files
+      .lock()
+      .unwrap()
+      .remove(challenge.token.as_deref().unwrap());
+    if let Some(file) = file {
+      file.disarm();
+    }
+
+    match tokio::fs::remove_file(&path).await {
+      Ok(()) => {
+        debug!({ path = %path.display() }, "Removed http-01 challenge file.");
+        Ok(())
+      }
+      Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+      Err(err) => Err(Error::Other(Box::new(err))),
+    }
+  }
+}
+
+/// A challenge file that is removed when dropped, unless it is disarmed.
+#[derive(Debug)]
+struct ChallengeFile {
+  path: PathBuf,
+  armed: bool,
+}
+
+impl ChallengeFile {
+  fn new(path: PathBuf) -> Self {
+    ChallengeFile { path, armed: true }
+  }
+
+  /// Keep the file when this guard is dropped.
+  fn disarm(mut self) {
+    self.armed = false;
+  }
+}
+
+impl Drop for ChallengeFile {
+  fn drop(&mut self) {
+    if !self.armed {
+      return;
+    }
+    match std::fs::remove_file(&self.path) {
+      Ok(()) => {}
+      Err(err) if err.kind() == ErrorKind::NotFound => {}
+      Err(err) => warn!(
+        { path = %self.path.display(), error = %err },
+        "Failed to remove challenge file."
+      ),
+    }
+  }
+}
+
+fn map_io_err<T>(res: std::io::Result<T>) -> Result<T, Error> {
+  res.map_err(|err| Error::Other(Box::new(err)))
+}
+
+/// Create the challenge directory, making new directories readable for the
+/// web server.
+async fn create_dir_all(dir: &Path) -> Result<(), Error> {
+  let mut builder = tokio::fs::DirBuilder::new();
+  builder.recursive(true);
+  #[cfg(unix)]
+  builder.mode(0o755);
+  map_io_err(builder.create(dir).await)
+}
+
+/// Create a new file that is readable, but not writable, for the web server.
+async fn write_file(path: &Path, contents: &[u8]) -> Result<(), Error> {
+  let mut options = tokio::fs::OpenOptions::new();
+  options.write(true).create_new(true);
+  let mut file = map_io_err(options.open(path).await)?;
+  // Set the mode explicitly, as the mode passed to open is subject to the
+  // umask of the process.
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    let permissions = std::fs::Permissions::from_mode(0o644);
+    map_io_err(file.set_permissions(permissions).await)?;
+  }
+  map_io_err(file.write_all(contents).await)?;
+  map_io_err(file.sync_all().await)
+}